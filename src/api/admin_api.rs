@@ -0,0 +1,99 @@
+use actix_web::{
+    delete, get, post, put,
+    web::{Data, Json, Path, Query},
+    HttpResponse,
+};
+use serde::Deserialize;
+
+use crate::ctx::AuthUser;
+use crate::model::api_key_model::{ApiKeyBMC, Scope};
+use crate::model::user_model::{Role, UserBMC, UserField};
+use crate::prelude::*;
+use crate::repository::filter::Filter;
+use crate::repository::page::PageParams;
+use crate::repository::sort::SortParams;
+use crate::repository::surrealdb_repo::{Repository, SurrealDBRepo};
+
+#[get("/users/inactive")]
+pub async fn get_inactive_users(
+    db: Data<SurrealDBRepo>,
+    sort_params: Query<SortParams>,
+    page_params: Query<PageParams>,
+) -> Result<HttpResponse, Error> {
+    let filter = Filter::Eq("isInactive".into(), true.into());
+    let sort = sort_params.parse::<UserField>()?;
+
+    let result = UserBMC::list(db, Some(&filter), sort, &page_params).await?;
+
+    Ok(HttpResponse::Ok().json(result))
+}
+
+#[derive(Deserialize)]
+pub struct SetRole {
+    role: Role,
+}
+
+#[put("/users/{id}/role")]
+pub async fn set_user_role(
+    db: Data<SurrealDBRepo>,
+    path: Path<String>,
+    body: Json<SetRole>,
+) -> Result<HttpResponse, Error> {
+    let id = path.into_inner();
+
+    let result = UserBMC::set_role(db, &id, body.role).await?;
+
+    Ok(HttpResponse::Ok().json(result))
+}
+
+#[derive(Deserialize)]
+pub struct CreateApiKey {
+    name: String,
+    scopes: Vec<Scope>,
+    /// User the key acts for, the admin creating it when missing
+    user_id: Option<String>,
+}
+
+#[post("/api-keys")]
+pub async fn create_api_key(
+    db: Data<SurrealDBRepo>,
+    auth: AuthUser,
+    body: Json<CreateApiKey>,
+) -> Result<HttpResponse, Error> {
+    let name = body.name.trim();
+
+    if name.is_empty() {
+        return Err(Error::InvalidInput("name must not be empty".into()));
+    }
+
+    if body.scopes.is_empty() {
+        return Err(Error::InvalidInput("expected at least one scope".into()));
+    }
+
+    let user_id = body.user_id.as_deref().unwrap_or(auth.user_id());
+
+    UserBMC::get(db.clone(), user_id).await?;
+
+    let result = ApiKeyBMC::create(db, user_id, name, &body.scopes).await?;
+
+    Ok(HttpResponse::Ok().json(result))
+}
+
+#[get("/api-keys")]
+pub async fn get_api_keys(db: Data<SurrealDBRepo>) -> Result<HttpResponse, Error> {
+    let result = ApiKeyBMC::list(db).await?;
+
+    Ok(HttpResponse::Ok().json(result))
+}
+
+#[delete("/api-keys/{id}")]
+pub async fn revoke_api_key(
+    db: Data<SurrealDBRepo>,
+    path: Path<String>,
+) -> Result<HttpResponse, Error> {
+    let id = path.into_inner();
+
+    let result = ApiKeyBMC::revoke(db, &id).await?;
+
+    Ok(HttpResponse::Ok().json(result))
+}
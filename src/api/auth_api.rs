@@ -0,0 +1,349 @@
+use actix_web::{
+    get, post,
+    web::{Data, Json},
+    HttpResponse,
+};
+use ed25519_dalek::{Signature, VerifyingKey};
+use serde::Deserialize;
+use std::time::Duration;
+
+use crate::config::Config;
+use crate::ctx::AuthUser;
+use crate::jwt::Jwt;
+use crate::model::auth_model::{AuthBMC, Tokens};
+use crate::model::user_model::UserBMC;
+use crate::prelude::*;
+use crate::repository::surrealdb_repo::{Repository, SearchOp, SurrealDBRepo};
+
+#[derive(Deserialize)]
+pub struct ChallengeRequest {
+    public_key: String,
+}
+
+#[post("/auth/challenge")]
+pub async fn create_challenge(
+    db: Data<SurrealDBRepo>,
+    body: Json<ChallengeRequest>,
+) -> Result<HttpResponse, Error> {
+    let public_key = body.public_key.trim();
+
+    // Refuse keys that could never verify a signature before issuing a nonce
+    decode_hex::<32>("public_key", public_key)?;
+
+    let user = UserBMC::search_by(db.clone(), "publicKey", SearchOp::Eq, public_key, &[])
+        .await?
+        .into_iter()
+        .next()
+        .ok_or(Error::AuthFail("unknown public key"))?;
+
+    let user_id = user.id.unwrap_or_default();
+    let challenge = AuthBMC::create_challenge(db, &user_id, public_key).await?;
+
+    Ok(HttpResponse::Ok().json(challenge))
+}
+
+#[derive(Deserialize)]
+pub struct VerifyRequest {
+    nonce: String,
+    signature: String,
+}
+
+#[post("/auth/verify")]
+pub async fn verify_challenge(
+    db: Data<SurrealDBRepo>,
+    config: Data<Config>,
+    jwt: Data<Jwt>,
+    body: Json<VerifyRequest>,
+) -> Result<HttpResponse, Error> {
+    let signature = Signature::from_bytes(&decode_hex::<64>("signature", &body.signature)?);
+
+    let challenge = AuthBMC::take_challenge(db.clone(), &body.nonce)
+        .await?
+        .ok_or(Error::AuthFail("unknown or expired challenge"))?;
+
+    let key = VerifyingKey::from_bytes(&decode_hex::<32>("public_key", &challenge.public_key)?)
+        .map_err(|_| Error::AuthFail("invalid public key"))?;
+
+    key.verify_strict(body.nonce.as_bytes(), &signature)
+        .map_err(|_| Error::AuthFail("invalid signature"))?;
+
+    let tokens = sign_in(db, &config, &jwt, &challenge.user_id).await?;
+
+    Ok(HttpResponse::Ok().json(tokens))
+}
+
+#[derive(Deserialize)]
+pub struct RefreshRequest {
+    refresh_token: String,
+}
+
+#[post("/auth/refresh")]
+pub async fn refresh_tokens(
+    db: Data<SurrealDBRepo>,
+    config: Data<Config>,
+    jwt: Data<Jwt>,
+    body: Json<RefreshRequest>,
+) -> Result<HttpResponse, Error> {
+    // Refresh tokens are single use, a new one is issued with every pair
+    let user_id = AuthBMC::take_session(db.clone(), &body.refresh_token)
+        .await?
+        .ok_or(Error::AuthFail("unknown or expired refresh token"))?;
+
+    let tokens = sign_in(db, &config, &jwt, &user_id).await?;
+
+    Ok(HttpResponse::Ok().json(tokens))
+}
+
+#[get("/auth/me")]
+pub async fn get_me(db: Data<SurrealDBRepo>, auth: AuthUser) -> Result<HttpResponse, Error> {
+    let user = UserBMC::get(db, auth.user_id()).await?;
+
+    Ok(HttpResponse::Ok().json(user))
+}
+
+/// Issues an access token and opens a refresh session for `user_id`, the
+/// current role of the user is read at every refresh
+async fn sign_in(
+    db: Data<SurrealDBRepo>,
+    config: &Config,
+    jwt: &Jwt,
+    user_id: &str,
+) -> Result<Tokens, Error> {
+    let user = UserBMC::get(db.clone(), user_id).await?;
+
+    let refresh_token =
+        AuthBMC::create_session(db, user_id, Duration::from_secs(config.refresh_token_ttl)).await?;
+
+    Ok(Tokens {
+        access_token: jwt.issue(user_id, user.role)?,
+        token_type: "Bearer",
+        expires_in: jwt.ttl(),
+        refresh_token,
+        refresh_expires_in: config.refresh_token_ttl,
+    })
+}
+
+/// Decodes `N` hex encoded bytes, with or without a `0x` prefix
+fn decode_hex<const N: usize>(field: &'static str, value: &str) -> Result<[u8; N], Error> {
+    let value = value.strip_prefix("0x").unwrap_or(value);
+    let mut bytes = [0u8; N];
+
+    hex::decode_to_slice(value, &mut bytes).map_err(|_| {
+        Error::InvalidFieldValue(field, "hex encoded bytes of an Ed25519 key or signature")
+    })?;
+
+    Ok(bytes)
+}
+
+#[cfg(all(test, feature = "kv-mem"))]
+mod tests {
+    use actix_web::{
+        http::StatusCode,
+        test::{call_service, init_service, read_body_json, TestRequest},
+        App,
+    };
+    use ed25519_dalek::{Signer, SigningKey};
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::model::user_model::{Role, User};
+
+    macro_rules! app {
+        () => {{
+            let db = Data::new(SurrealDBRepo::in_memory().await.unwrap());
+            let config = Config::fixture();
+            let jwt = Data::new(Jwt::from_config(&config).unwrap());
+            let app = init_service(
+                App::new()
+                    .app_data(db.clone())
+                    .app_data(Data::new(config))
+                    .app_data(jwt.clone())
+                    .service(create_challenge)
+                    .service(verify_challenge)
+                    .service(refresh_tokens)
+                    .service(get_me),
+            )
+            .await;
+            (app, db, jwt)
+        }};
+    }
+
+    /// Stores a user holding the public key of a fresh signing key
+    async fn user_with_key(db: &Data<SurrealDBRepo>) -> (String, SigningKey) {
+        let signing_key = SigningKey::from_bytes(&rand::random::<[u8; 32]>());
+        let user = UserBMC::create(
+            db.clone(),
+            User {
+                public_key: hex::encode(signing_key.verifying_key().to_bytes()),
+                ..User::fixture("signer")
+            },
+        )
+        .await
+        .unwrap();
+        (user.id.unwrap(), signing_key)
+    }
+
+    fn post(path: &str, body: Value) -> TestRequest {
+        TestRequest::post().uri(path).set_json(body)
+    }
+
+    fn challenge_of(key: &SigningKey) -> TestRequest {
+        post(
+            "/auth/challenge",
+            json!({ "public_key": hex::encode(key.verifying_key().to_bytes()) }),
+        )
+    }
+
+    fn verify(nonce: &str, key: &SigningKey) -> TestRequest {
+        post(
+            "/auth/verify",
+            json!({ "nonce": nonce, "signature": hex::encode(key.sign(nonce.as_bytes()).to_bytes()) }),
+        )
+    }
+
+    #[actix_web::test]
+    async fn signed_challenges_sign_the_user_in() {
+        let (app, db, jwt) = app!();
+        let (user_id, key) = user_with_key(&db).await;
+
+        let res = call_service(&app, challenge_of(&key).to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let challenge: Value = read_body_json(res).await;
+
+        let nonce = challenge["nonce"].as_str().unwrap();
+        let res = call_service(&app, verify(nonce, &key).to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let tokens: Value = read_body_json(res).await;
+
+        let claims = jwt
+            .validate(tokens["access_token"].as_str().unwrap())
+            .unwrap();
+        assert_eq!(claims.sub, user_id);
+        assert_eq!(tokens["token_type"], "Bearer");
+    }
+
+    #[actix_web::test]
+    async fn challenges_are_single_use() {
+        let (app, db, _) = app!();
+        let (_, key) = user_with_key(&db).await;
+        let challenge: Value =
+            read_body_json(call_service(&app, challenge_of(&key).to_request()).await).await;
+        let nonce = challenge["nonce"].as_str().unwrap();
+
+        let first = call_service(&app, verify(nonce, &key).to_request()).await;
+        let reused = call_service(&app, verify(nonce, &key).to_request()).await;
+
+        assert_eq!(first.status(), StatusCode::OK);
+        assert_eq!(reused.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[actix_web::test]
+    async fn signatures_of_another_key_are_rejected_and_burn_the_challenge() {
+        let (app, db, _) = app!();
+        let (_, key) = user_with_key(&db).await;
+        let other = SigningKey::from_bytes(&rand::random::<[u8; 32]>());
+        let challenge: Value =
+            read_body_json(call_service(&app, challenge_of(&key).to_request()).await).await;
+        let nonce = challenge["nonce"].as_str().unwrap();
+
+        let forged = call_service(&app, verify(nonce, &other).to_request()).await;
+        let retried = call_service(&app, verify(nonce, &key).to_request()).await;
+
+        assert_eq!(forged.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(retried.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[actix_web::test]
+    async fn challenges_need_a_known_valid_public_key() {
+        let (app, _, _) = app!();
+        let unknown = SigningKey::from_bytes(&rand::random::<[u8; 32]>());
+
+        let unknown = call_service(&app, challenge_of(&unknown).to_request()).await;
+        let malformed = call_service(
+            &app,
+            post("/auth/challenge", json!({ "public_key": "xyz" })).to_request(),
+        )
+        .await;
+
+        assert_eq!(unknown.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(malformed.status(), StatusCode::BAD_REQUEST);
+    }
+
+    /// Signs the user of `key` in and returns their tokens
+    macro_rules! sign_in {
+        ($app:expr, $key:expr) => {{
+            let challenge: Value =
+                read_body_json(call_service(&$app, challenge_of($key).to_request()).await).await;
+            let nonce = challenge["nonce"].as_str().unwrap();
+            let tokens: Value =
+                read_body_json(call_service(&$app, verify(nonce, $key).to_request()).await).await;
+            tokens
+        }};
+    }
+
+    fn refresh(tokens: &Value) -> TestRequest {
+        post(
+            "/auth/refresh",
+            json!({ "refresh_token": tokens["refresh_token"] }),
+        )
+    }
+
+    fn me(token: &str) -> TestRequest {
+        TestRequest::get()
+            .uri("/auth/me")
+            .insert_header(("Authorization", format!("Bearer {}", token)))
+    }
+
+    #[actix_web::test]
+    async fn refresh_tokens_rotate_and_are_single_use() {
+        let (app, db, _) = app!();
+        let (_, key) = user_with_key(&db).await;
+        let tokens = sign_in!(app, &key);
+
+        let res = call_service(&app, refresh(&tokens).to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let rotated: Value = read_body_json(res).await;
+        let reused = call_service(&app, refresh(&tokens).to_request()).await;
+        let next = call_service(&app, refresh(&rotated).to_request()).await;
+
+        assert_ne!(rotated["refresh_token"], tokens["refresh_token"]);
+        assert_eq!(reused.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(next.status(), StatusCode::OK);
+    }
+
+    #[actix_web::test]
+    async fn refreshed_access_tokens_carry_the_current_role() {
+        let (app, db, jwt) = app!();
+        let (user_id, key) = user_with_key(&db).await;
+        let tokens = sign_in!(app, &key);
+
+        UserBMC::set_role(db.clone(), &user_id, Role::Moderator)
+            .await
+            .unwrap();
+        let rotated: Value =
+            read_body_json(call_service(&app, refresh(&tokens).to_request()).await).await;
+
+        let claims = jwt
+            .validate(rotated["access_token"].as_str().unwrap())
+            .unwrap();
+        assert_eq!(claims.role, Role::Moderator);
+    }
+
+    #[actix_web::test]
+    async fn access_tokens_authenticate_requests() {
+        let (app, db, _) = app!();
+        let (user_id, key) = user_with_key(&db).await;
+        let tokens = sign_in!(app, &key);
+        let token = tokens["access_token"].as_str().unwrap();
+
+        let res = call_service(&app, me(token).to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let user: Value = read_body_json(res).await;
+        let tampered = call_service(&app, me(&format!("{}x", token)).to_request()).await;
+        let anonymous = call_service(&app, TestRequest::get().uri("/auth/me").to_request()).await;
+
+        assert_eq!(user["id"], user_id.as_str());
+        assert_eq!(tampered.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(anonymous.status(), StatusCode::UNAUTHORIZED);
+    }
+}
@@ -1,22 +1,35 @@
 use actix_web::{
-    delete, get, post, put,
-    web::{Data, Json, Path},
-    HttpResponse,
+    delete, get, patch, post, put,
+    web::{Data, Json, Path, Query},
+    HttpRequest, HttpResponse,
 };
 use serde::Deserialize;
-use serde_json::Value;
 
-use crate::model::item_model::{Item, ItemBMC, ItemPatch};
-use crate::repository::surrealdb_repo::SurrealDBRepo;
+use crate::api::patch::Patch;
+use crate::api::response::conditional;
+use crate::ctx::AuthUser;
+use crate::model::api_key_model::Scope;
+use crate::model::item_model::{Item, ItemBMC, ItemField};
+use crate::policy::{self, Owned};
+use crate::prelude::*;
+use crate::repository::filter::{DateRange, Filter};
+use crate::repository::page::PageParams;
+use crate::repository::sort::SortParams;
+use crate::repository::surrealdb_repo::{Repository, SurrealDBRepo};
+use crate::repository::version::{ExpectedVersion, Versioned};
 
 #[post("/items")]
-pub async fn create_item(db: Data<SurrealDBRepo>, new_item: Json<Item>) -> HttpResponse {
+pub async fn create_item(
+    db: Data<SurrealDBRepo>,
+    auth: AuthUser,
+    new_item: Json<Item>,
+) -> Result<HttpResponse, Error> {
     let data = Item {
         id: None,
         cid: new_item.cid.to_owned(),
         name: new_item.name.to_owned(),
         owner_id: new_item.owner_id.to_owned(),
-        version: new_item.version.to_owned(),
+        version: 0,
         content: new_item.content.to_owned(),
         image_url: new_item.image_url.to_owned(),
         creation_date: new_item.creation_date.to_owned(),
@@ -27,153 +40,241 @@ pub async fn create_item(db: Data<SurrealDBRepo>, new_item: Json<Item>) -> HttpR
         is_archived: new_item.is_archived.to_owned(),
     };
 
-    let item_detail = ItemBMC::create(db, "item", data).await;
+    auth.require_scope(Scope::WriteItems)?;
 
-    match item_detail {
-        Ok(item) => HttpResponse::Ok().json(item),
-        Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
+    if !policy::may_act_for(&auth, &data.owner_id) {
+        return Err(Error::Forbidden(
+            "items can only be created for yourself".into(),
+        ));
     }
+
+    let item_detail = ItemBMC::create(db, data).await?;
+
+    Ok(HttpResponse::Ok().json(item_detail))
 }
 
 #[get("/items/{id}")]
-pub async fn get_item(db: Data<SurrealDBRepo>, path: Path<String>) -> HttpResponse {
+pub async fn get_item(
+    db: Data<SurrealDBRepo>,
+    auth: Option<AuthUser>,
+    req: HttpRequest,
+    path: Path<String>,
+) -> Result<HttpResponse, Error> {
+    require_read(auth.as_ref())?;
+
     let id = path.into_inner();
 
     if id.is_empty() {
-        return HttpResponse::BadRequest().body("invalid ID");
+        return Err(Error::InvalidInput("invalid ID".into()));
     }
 
-    let item_detail = ItemBMC::get(db, &id).await;
+    let item_detail = ItemBMC::get(db, &id).await?;
 
-    match item_detail {
-        Ok(item) => HttpResponse::Ok().json(item),
-        Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
-    }
+    Ok(conditional(
+        &req,
+        item_detail.version,
+        Some(item_detail.last_modified()),
+        &item_detail,
+    ))
 }
 
 #[put("/items/{id}")]
 pub async fn update_item(
     db: Data<SurrealDBRepo>,
+    auth: AuthUser,
+    req: HttpRequest,
     path: Path<String>,
-    item_patch: Json<ItemPatch>,
-) -> HttpResponse {
+    body: Json<Versioned<Item>>,
+) -> Result<HttpResponse, Error> {
     let id = path.into_inner();
 
     if id.is_empty() {
-        return HttpResponse::BadRequest().body("invalid ID");
+        return Err(Error::InvalidInput("invalid ID".into()));
     };
 
-    let data = ItemPatch {
-        cid: item_patch.cid.to_owned(),
-        name: item_patch.name.to_owned(),
-        owner_id: item_patch.owner_id.to_owned(),
-        version: item_patch.version.to_owned(),
-        content: item_patch.content.to_owned(),
-        image_url: item_patch.image_url.to_owned(),
-        creation_date: item_patch.creation_date.to_owned(),
-        edition_date: item_patch.edition_date.to_owned(),
-        tag_ids: item_patch.tag_ids.to_owned(),
-        follower_ids: item_patch.follower_ids.to_owned(),
-        is_visible: item_patch.is_visible.to_owned(),
-        is_archived: item_patch.is_archived.to_owned(),
+    let Versioned {
+        version,
+        record: item,
+    } = body.into_inner();
+    let expected = ExpectedVersion::from_request(&req, version)?;
+
+    let data = Item {
+        id: None,
+        cid: item.cid.to_owned(),
+        name: item.name.to_owned(),
+        owner_id: item.owner_id.to_owned(),
+        version: expected.value(),
+        content: item.content.to_owned(),
+        image_url: item.image_url.to_owned(),
+        creation_date: item.creation_date.to_owned(),
+        edition_date: item.edition_date.to_owned(),
+        tag_ids: item.tag_ids.to_owned(),
+        follower_ids: item.follower_ids.to_owned(),
+        is_visible: item.is_visible.to_owned(),
+        is_archived: item.is_archived.to_owned(),
     };
 
-    let update_result = ItemBMC::update(db, &id, data).await;
+    let update_result = ItemBMC::replace_as(db, &auth, &id, data, expected).await?;
 
-    match update_result {
-        Ok(item) => HttpResponse::Ok().json(item),
-        Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
-    }
+    Ok(HttpResponse::Ok().json(update_result))
+}
+
+#[patch("/items/{id}")]
+pub async fn patch_item(
+    db: Data<SurrealDBRepo>,
+    auth: AuthUser,
+    req: HttpRequest,
+    path: Path<String>,
+    body: Json<serde_json::Value>,
+) -> Result<HttpResponse, Error> {
+    let id = path.into_inner();
+
+    if id.is_empty() {
+        return Err(Error::InvalidInput("invalid ID".into()));
+    };
+
+    let mut patch = Patch::from_request(&req, body.into_inner())?;
+    let expected = ExpectedVersion::from_request(&req, patch.version()?)?;
+
+    let update_result = ItemBMC::patch_as(db, &auth, &id, expected, |item| {
+        let mut doc = serde_json::to_value(item)?;
+        patch.apply(&mut doc)?;
+        serde_json::from_value(doc).map_err(|err| Error::InvalidPatch(err.to_string()))
+    })
+    .await?;
+
+    Ok(HttpResponse::Ok().json(update_result))
 }
 
 #[delete("/items/{id}")]
-pub async fn delete_item(db: Data<SurrealDBRepo>, path: Path<String>) -> HttpResponse {
+pub async fn delete_item(
+    db: Data<SurrealDBRepo>,
+    auth: AuthUser,
+    path: Path<String>,
+) -> Result<HttpResponse, Error> {
     let id = path.into_inner();
 
     if id.is_empty() {
-        return HttpResponse::BadRequest().body("invalid ID");
+        return Err(Error::InvalidInput("invalid ID".into()));
     };
 
-    let result = ItemBMC::delete(db, &id).await;
+    let result = ItemBMC::delete_as(db, &auth, &id).await?;
 
-    match result {
-        Ok(item) => HttpResponse::Ok().json(item),
-        Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
-    }
+    Ok(HttpResponse::Ok().json(result))
+}
+
+#[derive(Deserialize)]
+pub struct ListItems {
+    filter: Option<String>,
 }
 
 #[get("/items")]
-pub async fn get_items(db: Data<SurrealDBRepo>) -> HttpResponse {
-    let result = ItemBMC::get_all(db).await;
+pub async fn get_items(
+    db: Data<SurrealDBRepo>,
+    auth: Option<AuthUser>,
+    list_params: Query<ListItems>,
+    range_params: Query<DateRange>,
+    sort_params: Query<SortParams>,
+    page_params: Query<PageParams>,
+) -> Result<HttpResponse, Error> {
+    require_read(auth.as_ref())?;
 
-    match result {
-        Ok(items) => HttpResponse::Ok().json(items),
-        Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
-    }
+    let filter: Option<Filter> = match &list_params.filter {
+        Some(filter) => Some(
+            serde_json::from_str(filter).map_err(|err| Error::InvalidFilter(err.to_string()))?,
+        ),
+        None => None,
+    };
+    let filter = range_params.and::<ItemField>(filter)?;
+
+    let sort = sort_params.parse::<ItemField>()?;
+
+    let result = ItemBMC::list(db, filter.as_ref(), sort, &page_params).await?;
+
+    Ok(HttpResponse::Ok().json(result))
 }
 
 #[derive(Deserialize)]
 pub struct SearchItemsByIds {
-    ids: Vec<String>,
+    ids: String,
 }
 
 #[get("/itemsByIds")]
 pub async fn search_items_by_ids(
     db: Data<SurrealDBRepo>,
-    search_params: Json<SearchItemsByIds>,
-) -> HttpResponse {
-    let array_ids = search_params.ids.iter().map(|c| c.as_str()).collect();
-    let result = ItemBMC::search_by_ids(db, array_ids).await;
-
-    match result {
-        Ok(items) => HttpResponse::Ok().json(items),
-        Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
+    auth: Option<AuthUser>,
+    search_params: Query<SearchItemsByIds>,
+    sort_params: Query<SortParams>,
+) -> Result<HttpResponse, Error> {
+    require_read(auth.as_ref())?;
+
+    let sort = sort_params.parse::<ItemField>()?;
+
+    let array_ids: Vec<&str> = search_params
+        .ids
+        .split(',')
+        .map(str::trim)
+        .filter(|id| !id.is_empty())
+        .collect();
+
+    if array_ids.is_empty() {
+        return Err(Error::InvalidInput("expected at least one id".into()));
     }
-}
 
-#[derive(Deserialize)]
-pub struct SearchItemsBy {
-    param: (String, Value),
+    let result = ItemBMC::search_by_ids(db, array_ids, &sort).await?;
+
+    Ok(HttpResponse::Ok().json(result))
 }
 
 #[get("/itemsBy")]
 pub async fn search_items_by(
     db: Data<SurrealDBRepo>,
-    search_params: Json<SearchItemsBy>,
-) -> HttpResponse {
-    let key = search_params.param.0.to_owned();
-    let value = search_params.param.1.to_owned();
-
-    let result = match key.as_str() {
-        "name" => ItemBMC::search_by_name(db, &value.as_str().unwrap_or("")).await,
-        "ownerId" => ItemBMC::search_by_owner_id(db, &value.as_str().unwrap_or("")).await,
-        "creationDate" => ItemBMC::search_by_creation_date(db, &value.as_str().unwrap_or("")).await,
-        "editionDate" => ItemBMC::search_by_edition_date(db, &value.as_str().unwrap_or("")).await,
-        "tagIds" => {
-            let tag_ids = match &value {
-                serde_json::Value::Array(arr) => {
-                    arr.iter().map(|c| c.as_str().unwrap_or("")).collect()
-                }
-                _ => Vec::new(),
-            };
-            ItemBMC::search_by_tag_ids(db, tag_ids).await
-        }
-        "followerIds" => {
-            let follower_ids = match &value {
-                serde_json::Value::Array(arr) => {
-                    arr.iter().map(|c| c.as_str().unwrap_or("")).collect()
-                }
-                _ => Vec::new(),
-            };
-            ItemBMC::search_by_follower_ids(db, follower_ids).await
-        }
-        "isVisible" => ItemBMC::search_by_is_visible(db, value.as_bool().unwrap_or(false)).await,
-        "isArchived" => ItemBMC::search_by_is_archived(db, value.as_bool().unwrap_or(false)).await,
-        _ => panic!("Invalid key"),
-    };
+    auth: Option<AuthUser>,
+    search_params: Query<Vec<(String, String)>>,
+    range_params: Query<DateRange>,
+    sort_params: Query<SortParams>,
+    page_params: Query<PageParams>,
+) -> Result<HttpResponse, Error> {
+    require_read(auth.as_ref())?;
+
+    let filter = Filter::from_query::<ItemField>(&search_params)?;
+    let filter = range_params.and::<ItemField>(Some(filter))?;
+    let sort = sort_params.parse::<ItemField>()?;
+
+    let result = ItemBMC::list(db, filter.as_ref(), sort, &page_params).await?;
+
+    Ok(HttpResponse::Ok().json(result))
+}
+
+#[derive(Deserialize)]
+pub struct SearchItems {
+    filter: Option<Filter>,
+    #[serde(flatten)]
+    sort: SortParams,
+    #[serde(flatten)]
+    page: PageParams,
+}
+
+#[post("/items/search")]
+pub async fn search_items(
+    db: Data<SurrealDBRepo>,
+    auth: Option<AuthUser>,
+    search_params: Json<SearchItems>,
+) -> Result<HttpResponse, Error> {
+    require_read(auth.as_ref())?;
+
+    let sort = search_params.sort.parse::<ItemField>()?;
+
+    let result =
+        ItemBMC::list(db, search_params.filter.as_ref(), sort, &search_params.page).await?;
+
+    Ok(HttpResponse::Ok().json(result))
+}
 
-    match result {
-        Ok(users) => HttpResponse::Ok().json(users),
-        Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
+/// Items are public, callers with an API key still need the `read:items` scope
+fn require_read(auth: Option<&AuthUser>) -> Result<(), Error> {
+    match auth {
+        Some(auth) => auth.require_scope(Scope::ReadItems),
+        None => Ok(()),
     }
 }
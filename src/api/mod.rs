@@ -1,24 +1,93 @@
 // api/mod.rs
-use actix_web::web;
+use actix_web::{http::Method, web};
+pub mod admin_api;
+pub mod auth_api;
 pub mod item_api;
+pub mod moderation_api;
+pub mod patch;
+pub mod response;
 pub mod user_api;
 
+use crate::config::Config;
+use crate::ctx::RequireRole;
+use crate::model::user_model::Role;
+use crate::prelude::*;
+use crate::rate_limit::RateLimiter;
+
+/// Limits every route to `RATE_LIMIT`, creating and searching, the most
+/// expensive routes, to `RATE_LIMIT_STRICT`
+pub fn rate_limiter(config: &Config) -> RateLimiter {
+    RateLimiter::new(config.rate_limit)
+        .group(
+            "create",
+            config.rate_limit_strict,
+            &[
+                (Method::POST, "/api/items"),
+                (Method::POST, "/api/users"),
+                (Method::POST, "/api/auth/challenge"),
+                (Method::POST, "/api/admin/api-keys"),
+            ],
+        )
+        .group(
+            "search",
+            config.rate_limit_strict,
+            &[
+                (Method::GET, "/api/itemsByIds"),
+                (Method::GET, "/api/itemsBy"),
+                (Method::POST, "/api/items/search"),
+                (Method::GET, "/api/usersByIds"),
+                (Method::GET, "/api/usersBy"),
+                (Method::POST, "/api/users/search"),
+            ],
+        )
+}
+
 pub fn config(conf: &mut web::ServiceConfig) {
+    let admin = web::scope("/admin")
+        .wrap(RequireRole(Role::Admin))
+        .service(admin_api::get_inactive_users)
+        .service(admin_api::set_user_role)
+        .service(admin_api::create_api_key)
+        .service(admin_api::get_api_keys)
+        .service(admin_api::revoke_api_key);
+
+    let moderation = web::scope("/moderation")
+        .wrap(RequireRole(Role::Moderator))
+        .service(moderation_api::set_user_visibility)
+        .service(moderation_api::set_item_visibility)
+        .service(moderation_api::archive_item);
+
     let scope = web::scope("/api")
+        .service(admin)
+        .service(moderation)
+        .service(auth_api::create_challenge)
+        .service(auth_api::verify_challenge)
+        .service(auth_api::refresh_tokens)
+        .service(auth_api::get_me)
         .service(item_api::create_item)
         .service(item_api::get_item)
         .service(item_api::get_items)
         .service(item_api::update_item)
+        .service(item_api::patch_item)
         .service(item_api::delete_item)
         .service(item_api::search_items_by_ids)
         .service(item_api::search_items_by)
+        .service(item_api::search_items)
         .service(user_api::create_user)
         .service(user_api::get_user)
         .service(user_api::get_users)
         .service(user_api::update_user)
+        .service(user_api::patch_user)
         .service(user_api::delete_user)
         .service(user_api::search_users_by_ids)
-        .service(user_api::search_users_by);
+        .service(user_api::search_users_by)
+        .service(user_api::search_users);
 
-    conf.service(scope);
+    conf.app_data(
+        web::JsonConfig::default().error_handler(|err, _req| Error::Actix(err.into()).into()),
+    )
+    .app_data(
+        web::QueryConfig::default().error_handler(|err, _req| Error::Actix(err.into()).into()),
+    )
+    .service(scope);
 }
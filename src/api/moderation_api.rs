@@ -0,0 +1,160 @@
+use actix_web::{
+    post, put,
+    web::{Data, Json, Path},
+    HttpResponse,
+};
+use serde::Deserialize;
+
+use crate::model::item_model::{ItemBMC, ItemPatch};
+use crate::model::user_model::{UserBMC, UserPatch};
+use crate::prelude::*;
+use crate::repository::surrealdb_repo::{Repository, SurrealDBRepo};
+use crate::repository::version::ExpectedVersion;
+
+#[derive(Deserialize)]
+pub struct SetVisibility {
+    is_visible: bool,
+}
+
+#[put("/users/{id}/visibility")]
+pub async fn set_user_visibility(
+    db: Data<SurrealDBRepo>,
+    path: Path<String>,
+    body: Json<SetVisibility>,
+) -> Result<HttpResponse, Error> {
+    let id = path.into_inner();
+
+    let user = UserBMC::get(db.clone(), &id).await?;
+
+    let data = UserPatch {
+        is_visible: Some(body.is_visible),
+        ..Default::default()
+    };
+
+    let result = UserBMC::update(db, &id, data, ExpectedVersion::Read(user.version)).await?;
+
+    Ok(HttpResponse::Ok().json(result))
+}
+
+#[put("/items/{id}/visibility")]
+pub async fn set_item_visibility(
+    db: Data<SurrealDBRepo>,
+    path: Path<String>,
+    body: Json<SetVisibility>,
+) -> Result<HttpResponse, Error> {
+    let id = path.into_inner();
+
+    let item = ItemBMC::get(db.clone(), &id).await?;
+
+    let data = ItemPatch {
+        is_visible: Some(body.is_visible),
+        ..Default::default()
+    };
+
+    let result = ItemBMC::update(db, &id, data, ExpectedVersion::Read(item.version)).await?;
+
+    Ok(HttpResponse::Ok().json(result))
+}
+
+#[post("/items/{id}/archive")]
+pub async fn archive_item(
+    db: Data<SurrealDBRepo>,
+    path: Path<String>,
+) -> Result<HttpResponse, Error> {
+    let id = path.into_inner();
+
+    let item = ItemBMC::get(db.clone(), &id).await?;
+
+    let data = ItemPatch {
+        is_archived: Some(true),
+        ..Default::default()
+    };
+
+    let result = ItemBMC::update(db, &id, data, ExpectedVersion::Read(item.version)).await?;
+
+    Ok(HttpResponse::Ok().json(result))
+}
+
+#[cfg(all(test, feature = "kv-mem"))]
+mod tests {
+    use actix_web::{
+        http::StatusCode,
+        test::{call_service, init_service, read_body_json, TestRequest},
+        App,
+    };
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::config::Config;
+    use crate::jwt::Jwt;
+    use crate::model::item_model::Item;
+    use crate::model::user_model::Role;
+
+    macro_rules! app {
+        () => {{
+            let db = Data::new(SurrealDBRepo::in_memory().await.unwrap());
+            let jwt = Data::new(Jwt::from_config(&Config::fixture()).unwrap());
+            let app = init_service(
+                App::new()
+                    .app_data(db.clone())
+                    .app_data(jwt.clone())
+                    .configure(crate::api::config),
+            )
+            .await;
+            (app, db, jwt)
+        }};
+    }
+
+    fn as_role(req: TestRequest, jwt: &Jwt, role: Role) -> TestRequest {
+        let token = jwt.issue("caller", role).unwrap();
+        req.insert_header(("Authorization", format!("Bearer {}", token)))
+    }
+
+    #[actix_web::test]
+    async fn moderators_hide_and_archive_items() {
+        let (app, db, jwt) = app!();
+        let id = ItemBMC::create(db.clone(), Item::fixture("owner", "name"))
+            .await
+            .unwrap()
+            .id
+            .unwrap();
+
+        let hide = TestRequest::put()
+            .uri(&format!("/api/moderation/items/{}/visibility", id))
+            .set_json(json!({ "is_visible": false }));
+        let res = call_service(&app, as_role(hide, &jwt, Role::Moderator).to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let hidden: Value = read_body_json(res).await;
+
+        let archive = TestRequest::post().uri(&format!("/api/moderation/items/{}/archive", id));
+        let res = call_service(&app, as_role(archive, &jwt, Role::Admin).to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let archived: Value = read_body_json(res).await;
+
+        assert_eq!(hidden["is_visible"], false);
+        assert_eq!(archived["is_archived"], true);
+    }
+
+    #[actix_web::test]
+    async fn users_cannot_moderate_and_moderators_cannot_administer() {
+        let (app, db, jwt) = app!();
+        let id = ItemBMC::create(db.clone(), Item::fixture("owner", "name"))
+            .await
+            .unwrap()
+            .id
+            .unwrap();
+
+        let archive = TestRequest::post().uri(&format!("/api/moderation/items/{}/archive", id));
+        let keys = TestRequest::get().uri("/api/admin/api-keys");
+        let anonymous = TestRequest::post().uri(&format!("/api/moderation/items/{}/archive", id));
+
+        let archive = call_service(&app, as_role(archive, &jwt, Role::User).to_request()).await;
+        let keys = call_service(&app, as_role(keys, &jwt, Role::Moderator).to_request()).await;
+        let anonymous = call_service(&app, anonymous.to_request()).await;
+
+        assert_eq!(archive.status(), StatusCode::FORBIDDEN);
+        assert_eq!(keys.status(), StatusCode::FORBIDDEN);
+        assert_eq!(anonymous.status(), StatusCode::UNAUTHORIZED);
+        assert!(!ItemBMC::get(db, &id).await.unwrap().is_archived);
+    }
+}
@@ -0,0 +1,507 @@
+use actix_web::{http::header, HttpRequest};
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::prelude::*;
+use crate::repository::version::{MAX_VERSION, VERSION_RANGE};
+
+const JSON_PATCH: &str = "application/json-patch+json";
+
+/// Body of the `PATCH` endpoints, picked by its `Content-Type`
+///
+/// `application/json-patch+json` bodies are JSON Patches (RFC 6902), any
+/// other JSON body a JSON Merge Patch (RFC 7396). Both apply to the record as
+/// the API returns it, with snake_case members.
+///
+/// ## Usage
+/// ```no_run
+/// let mut patch = Patch::from_request(&req, body.into_inner())?;
+/// let expected = ExpectedVersion::from_request(&req, patch.version()?)?;
+///
+/// let mut doc = serde_json::to_value(&item)?;
+/// patch.apply(&mut doc)?;
+/// ```
+#[derive(Debug)]
+pub enum Patch {
+    Merge(Value),
+    Json(Vec<Operation>),
+}
+
+/// Operation of a JSON Patch, paths are JSON Pointers (RFC 6901)
+#[derive(Debug, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum Operation {
+    Add { path: String, value: Value },
+    Remove { path: String },
+    Replace { path: String, value: Value },
+    Move { from: String, path: String },
+    Copy { from: String, path: String },
+    Test { path: String, value: Value },
+}
+
+impl Patch {
+    pub fn from_request(req: &HttpRequest, body: Value) -> Result<Self, Error> {
+        let json_patch = req
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.split(';').next())
+            .is_some_and(|mime| mime.trim().eq_ignore_ascii_case(JSON_PATCH));
+
+        match (json_patch, body) {
+            (true, body) => serde_json::from_value(body)
+                .map(Patch::Json)
+                .map_err(|err| Error::InvalidPatch(err.to_string())),
+            (false, body @ Value::Object(_)) => Ok(Patch::Merge(body)),
+            (false, _) => Err(Error::InvalidPatch(
+                "a merge patch must be a JSON object".into(),
+            )),
+        }
+    }
+
+    /// Takes the `version` member out of a merge patch, it is the version
+    /// the client expects the record at rather than a change
+    pub fn version(&mut self) -> Result<Option<u64>, Error> {
+        match self {
+            Patch::Merge(Value::Object(patch)) => match patch.remove("version") {
+                Some(version) => version
+                    .as_u64()
+                    .filter(|version| *version <= MAX_VERSION)
+                    .map(Some)
+                    .ok_or(Error::InvalidFieldValue("version", VERSION_RANGE)),
+                None => Ok(None),
+            },
+            _ => Ok(None),
+        }
+    }
+
+    pub fn apply(self, doc: &mut Value) -> Result<(), Error> {
+        match self {
+            Patch::Merge(patch) => {
+                merge(doc, patch);
+                Ok(())
+            }
+            Patch::Json(operations) => operations
+                .into_iter()
+                .try_for_each(|operation| operation.apply(doc)),
+        }
+    }
+}
+
+/// Applies a JSON Merge Patch, `null` members are removed from `doc`
+fn merge(doc: &mut Value, patch: Value) {
+    let Value::Object(patch) = patch else {
+        *doc = patch;
+        return;
+    };
+
+    if !doc.is_object() {
+        *doc = Value::Object(Default::default());
+    }
+
+    if let Value::Object(doc) = doc {
+        for (key, value) in patch {
+            match value {
+                Value::Null => {
+                    doc.remove(&key);
+                }
+                value => merge(doc.entry(key).or_insert(Value::Null), value),
+            }
+        }
+    }
+}
+
+impl Operation {
+    fn apply(self, doc: &mut Value) -> Result<(), Error> {
+        match self {
+            Operation::Add { path, value } => add(doc, &path, value),
+            Operation::Remove { path } => remove(doc, &path).map(drop),
+            Operation::Replace { path, value } => {
+                *get_mut(doc, &path)? = value;
+                Ok(())
+            }
+            Operation::Move { from, path } => {
+                if path.starts_with(&format!("{}/", from)) {
+                    return Err(Error::InvalidPatch(format!(
+                        "cannot move '{}' into itself",
+                        from
+                    )));
+                }
+
+                let value = remove(doc, &from)?;
+                add(doc, &path, value)
+            }
+            Operation::Copy { from, path } => {
+                let value = get_mut(doc, &from)?.clone();
+                add(doc, &path, value)
+            }
+            Operation::Test { path, value } => match *get_mut(doc, &path)? == value {
+                true => Ok(()),
+                false => Err(Error::PatchTestFailed(path)),
+            },
+        }
+    }
+}
+
+fn get_mut<'a>(doc: &'a mut Value, path: &str) -> Result<&'a mut Value, Error> {
+    doc.pointer_mut(path)
+        .ok_or_else(|| Error::InvalidPatch(format!("'{}' does not exist", path)))
+}
+
+/// Splits `path` into the pointer of its parent and its unescaped last token
+fn split(path: &str) -> Result<(&str, String), Error> {
+    let (parent, token) = path
+        .rsplit_once('/')
+        .ok_or_else(|| Error::InvalidPatch(format!("'{}' is not a JSON Pointer", path)))?;
+
+    Ok((parent, token.replace("~1", "/").replace("~0", "~")))
+}
+
+/// Index `token` of an array holding `len` elements, `len` itself is only
+/// valid where elements are added
+fn index(token: &str, len: usize) -> Result<usize, Error> {
+    token
+        .parse::<usize>()
+        .ok()
+        .filter(|index| *index < len && (token == "0" || !token.starts_with('0')))
+        .ok_or_else(|| Error::InvalidPatch(format!("'{}' is not a valid index", token)))
+}
+
+fn add(doc: &mut Value, path: &str, value: Value) -> Result<(), Error> {
+    if path.is_empty() {
+        *doc = value;
+        return Ok(());
+    }
+
+    let (parent, token) = split(path)?;
+
+    match get_mut(doc, parent)? {
+        Value::Object(obj) => {
+            obj.insert(token, value);
+            Ok(())
+        }
+        Value::Array(arr) => {
+            let index = match token.as_str() {
+                "-" => arr.len(),
+                token => index(token, arr.len() + 1)?,
+            };
+            arr.insert(index, value);
+            Ok(())
+        }
+        _ => Err(Error::InvalidPatch(format!(
+            "'{}' is not an object or an array",
+            parent
+        ))),
+    }
+}
+
+fn remove(doc: &mut Value, path: &str) -> Result<Value, Error> {
+    let (parent, token) = split(path)?;
+
+    match get_mut(doc, parent)? {
+        Value::Object(obj) => obj
+            .remove(&token)
+            .ok_or_else(|| Error::InvalidPatch(format!("'{}' does not exist", path))),
+        Value::Array(arr) => {
+            let index = index(&token, arr.len())?;
+            Ok(arr.remove(index))
+        }
+        _ => Err(Error::InvalidPatch(format!(
+            "'{}' is not an object or an array",
+            parent
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::TestRequest;
+    use serde_json::json;
+
+    use super::*;
+
+    fn json_patch(doc: Value, ops: Value) -> Result<Value, Error> {
+        let mut doc = doc;
+        Patch::Json(serde_json::from_value(ops).unwrap()).apply(&mut doc)?;
+        Ok(doc)
+    }
+
+    /// Examples of RFC 6902 appendix A applying cleanly
+    #[test]
+    fn json_patch_applies_rfc_6902_examples() {
+        let cases = [
+            (
+                "A.1 adding an object member",
+                json!({ "foo": "bar" }),
+                json!([{ "op": "add", "path": "/baz", "value": "qux" }]),
+                json!({ "baz": "qux", "foo": "bar" }),
+            ),
+            (
+                "A.2 adding an array element",
+                json!({ "foo": ["bar", "baz"] }),
+                json!([{ "op": "add", "path": "/foo/1", "value": "qux" }]),
+                json!({ "foo": ["bar", "qux", "baz"] }),
+            ),
+            (
+                "A.3 removing an object member",
+                json!({ "baz": "qux", "foo": "bar" }),
+                json!([{ "op": "remove", "path": "/baz" }]),
+                json!({ "foo": "bar" }),
+            ),
+            (
+                "A.4 removing an array element",
+                json!({ "foo": ["bar", "qux", "baz"] }),
+                json!([{ "op": "remove", "path": "/foo/1" }]),
+                json!({ "foo": ["bar", "baz"] }),
+            ),
+            (
+                "A.5 replacing a value",
+                json!({ "baz": "qux", "foo": "bar" }),
+                json!([{ "op": "replace", "path": "/baz", "value": "boo" }]),
+                json!({ "baz": "boo", "foo": "bar" }),
+            ),
+            (
+                "A.6 moving a value",
+                json!({ "foo": { "bar": "baz", "waldo": "fred" }, "qux": { "corge": "grault" } }),
+                json!([{ "op": "move", "from": "/foo/waldo", "path": "/qux/thud" }]),
+                json!({ "foo": { "bar": "baz" }, "qux": { "corge": "grault", "thud": "fred" } }),
+            ),
+            (
+                "A.7 moving an array element",
+                json!({ "foo": ["all", "grass", "cows", "eat"] }),
+                json!([{ "op": "move", "from": "/foo/1", "path": "/foo/3" }]),
+                json!({ "foo": ["all", "cows", "eat", "grass"] }),
+            ),
+            (
+                "A.8 testing a value: success",
+                json!({ "baz": "qux", "foo": ["a", 2, "c"] }),
+                json!([
+                    { "op": "test", "path": "/baz", "value": "qux" },
+                    { "op": "test", "path": "/foo/1", "value": 2 }
+                ]),
+                json!({ "baz": "qux", "foo": ["a", 2, "c"] }),
+            ),
+            (
+                "A.10 adding a nested member object",
+                json!({ "foo": "bar" }),
+                json!([{ "op": "add", "path": "/child", "value": { "grandchild": {} } }]),
+                json!({ "foo": "bar", "child": { "grandchild": {} } }),
+            ),
+            (
+                "A.11 ignoring unrecognized elements",
+                json!({ "foo": "bar" }),
+                json!([{ "op": "add", "path": "/baz", "value": "qux", "xyz": 123 }]),
+                json!({ "foo": "bar", "baz": "qux" }),
+            ),
+            (
+                "A.14 ~ escape ordering",
+                json!({ "/": 9, "~1": 10 }),
+                json!([{ "op": "test", "path": "/~01", "value": 10 }]),
+                json!({ "/": 9, "~1": 10 }),
+            ),
+            (
+                "A.16 adding an array value",
+                json!({ "foo": ["bar"] }),
+                json!([{ "op": "add", "path": "/foo/-", "value": ["abc", "def"] }]),
+                json!({ "foo": ["bar", ["abc", "def"]] }),
+            ),
+            (
+                "copying a value",
+                json!({ "foo": { "bar": 1 } }),
+                json!([{ "op": "copy", "from": "/foo", "path": "/baz" }]),
+                json!({ "foo": { "bar": 1 }, "baz": { "bar": 1 } }),
+            ),
+            (
+                "adding to an escaped member",
+                json!({}),
+                json!([{ "op": "add", "path": "/a~1b~0c", "value": 1 }]),
+                json!({ "a/b~c": 1 }),
+            ),
+            (
+                "adding after the last element",
+                json!({ "foo": [1] }),
+                json!([{ "op": "add", "path": "/foo/1", "value": 2 }]),
+                json!({ "foo": [1, 2] }),
+            ),
+            (
+                "replacing the whole document",
+                json!({ "foo": 1 }),
+                json!([{ "op": "add", "path": "", "value": { "bar": 2 } }]),
+                json!({ "bar": 2 }),
+            ),
+        ];
+
+        for (name, doc, ops, expected) in cases {
+            assert_eq!(json_patch(doc, ops).unwrap(), expected, "{}", name);
+        }
+    }
+
+    /// Examples of RFC 6902 appendix A that are errors, and the indexes and
+    /// moves it forbids
+    #[test]
+    fn json_patch_rejects_rfc_6902_errors() {
+        let cases = [
+            (
+                "A.9 testing a value: error",
+                json!({ "baz": "qux" }),
+                json!([{ "op": "test", "path": "/baz", "value": "bar" }]),
+            ),
+            (
+                "A.12 adding to a nonexistent target",
+                json!({ "foo": "bar" }),
+                json!([{ "op": "add", "path": "/baz/bat", "value": "qux" }]),
+            ),
+            (
+                "A.15 comparing strings and numbers",
+                json!({ "/": 9, "~1": 10 }),
+                json!([{ "op": "test", "path": "/~01", "value": "10" }]),
+            ),
+            (
+                "index with a leading zero",
+                json!({ "foo": [1, 2] }),
+                json!([{ "op": "remove", "path": "/foo/01" }]),
+            ),
+            (
+                "index past the end",
+                json!({ "foo": [1] }),
+                json!([{ "op": "add", "path": "/foo/2", "value": 2 }]),
+            ),
+            (
+                "removing past the last element",
+                json!({ "foo": [1] }),
+                json!([{ "op": "remove", "path": "/foo/1" }]),
+            ),
+            (
+                "removing a missing member",
+                json!({ "foo": 1 }),
+                json!([{ "op": "remove", "path": "/bar" }]),
+            ),
+            (
+                "replacing a missing member",
+                json!({ "foo": 1 }),
+                json!([{ "op": "replace", "path": "/bar", "value": 2 }]),
+            ),
+            (
+                "moving a value into itself",
+                json!({ "foo": { "bar": 1 } }),
+                json!([{ "op": "move", "from": "/foo", "path": "/foo/baz" }]),
+            ),
+            (
+                "adding below a scalar",
+                json!({ "foo": 1 }),
+                json!([{ "op": "add", "path": "/foo/bar", "value": 2 }]),
+            ),
+            (
+                "path that is not a pointer",
+                json!({ "foo": 1 }),
+                json!([{ "op": "add", "path": "foo", "value": 2 }]),
+            ),
+        ];
+
+        for (name, doc, ops) in cases {
+            assert!(
+                matches!(
+                    json_patch(doc, ops),
+                    Err(Error::InvalidPatch(_) | Error::PatchTestFailed(_))
+                ),
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn json_patch_test_failures_name_the_path() {
+        let result = json_patch(
+            json!({ "baz": "qux" }),
+            json!([{ "op": "test", "path": "/baz", "value": "bar" }]),
+        );
+
+        assert!(matches!(result, Err(Error::PatchTestFailed(path)) if path == "/baz"));
+    }
+
+    /// Examples of RFC 7396 appendix A
+    #[test]
+    fn merge_patch_applies_rfc_7396_examples() {
+        let cases = [
+            (json!({"a":"b"}), json!({"a":"c"}), json!({"a":"c"})),
+            (json!({"a":"b"}), json!({"b":"c"}), json!({"a":"b","b":"c"})),
+            (json!({"a":"b"}), json!({"a":null}), json!({})),
+            (
+                json!({"a":"b","b":"c"}),
+                json!({"a":null}),
+                json!({"b":"c"}),
+            ),
+            (json!({"a":["b"]}), json!({"a":"c"}), json!({"a":"c"})),
+            (json!({"a":"c"}), json!({"a":["b"]}), json!({"a":["b"]})),
+            (
+                json!({"a":{"b":"c"}}),
+                json!({"a":{"b":"d","c":null}}),
+                json!({"a":{"b":"d"}}),
+            ),
+            (json!({"a":[{"b":"c"}]}), json!({"a":[1]}), json!({"a":[1]})),
+            (json!(["a", "b"]), json!(["c", "d"]), json!(["c", "d"])),
+            (json!({"a":"b"}), json!(["c"]), json!(["c"])),
+            (json!({"a":"foo"}), json!(null), json!(null)),
+            (json!({"a":"foo"}), json!("bar"), json!("bar")),
+            (json!({"e":null}), json!({"a":1}), json!({"e":null,"a":1})),
+            (json!([1, 2]), json!({"a":"b","c":null}), json!({"a":"b"})),
+            (
+                json!({}),
+                json!({"a":{"bb":{"ccc":null}}}),
+                json!({"a":{"bb":{}}}),
+            ),
+        ];
+
+        for (doc, patch, expected) in cases {
+            let mut result = doc.clone();
+            Patch::Merge(patch.clone()).apply(&mut result).unwrap();
+            assert_eq!(result, expected, "{} merged with {}", doc, patch);
+        }
+    }
+
+    #[test]
+    fn from_request_picks_the_patch_by_content_type() {
+        let ops = json!([{ "op": "remove", "path": "/name" }]);
+        let json_patch = TestRequest::default()
+            .insert_header((
+                header::CONTENT_TYPE,
+                "application/json-patch+json; charset=utf-8",
+            ))
+            .to_http_request();
+        let merge_patch = TestRequest::default()
+            .insert_header((header::CONTENT_TYPE, "application/merge-patch+json"))
+            .to_http_request();
+
+        assert!(matches!(
+            Patch::from_request(&json_patch, ops.clone()),
+            Ok(Patch::Json(ops)) if ops.len() == 1
+        ));
+        assert!(matches!(
+            Patch::from_request(&json_patch, json!([{ "op": "frobnicate" }])),
+            Err(Error::InvalidPatch(_))
+        ));
+        assert!(matches!(
+            Patch::from_request(&merge_patch, json!({ "name": "x" })),
+            Ok(Patch::Merge(_))
+        ));
+        assert!(matches!(
+            Patch::from_request(&merge_patch, ops),
+            Err(Error::InvalidPatch(_))
+        ));
+    }
+
+    #[test]
+    fn version_is_taken_out_of_merge_patches() {
+        let mut patch = Patch::Merge(json!({ "name": "x", "version": 3 }));
+        let mut too_large = Patch::Merge(json!({ "version": MAX_VERSION + 1 }));
+        let mut negative = Patch::Merge(json!({ "version": -1 }));
+        let mut json_patch = Patch::Json(Vec::new());
+
+        assert_eq!(patch.version().unwrap(), Some(3));
+        assert!(matches!(patch, Patch::Merge(ref body) if *body == json!({ "name": "x" })));
+        assert!(too_large.version().is_err());
+        assert!(negative.version().is_err());
+        assert_eq!(json_patch.version().unwrap(), None);
+    }
+}
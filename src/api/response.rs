@@ -0,0 +1,360 @@
+// api/response.rs
+use actix_web::{
+    dev::{Service, ServiceRequest, ServiceResponse},
+    http::{
+        header::{
+            self, ETag, HeaderName, HeaderValue, HttpDate, IfModifiedSince, IfNoneMatch,
+            LastModified,
+        },
+        StatusCode,
+    },
+    HttpMessage, HttpRequest, HttpResponse, ResponseError,
+};
+use serde::Serialize;
+use std::future::Future;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use surrealdb::error::Db;
+
+use crate::prelude::*;
+use crate::repository::version::etag;
+
+const REQUEST_ID_HEADER: &str = "x-request-id";
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+/// JSON envelope returned by every failed request
+#[derive(Debug, Serialize)]
+pub struct Response {
+    pub code: u16,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
+impl Response {
+    pub fn new_error(code: u16, message: String) -> Self {
+        Response {
+            code,
+            message,
+            details: None,
+            request_id: request_id(),
+        }
+    }
+
+    pub fn with_details(mut self, details: serde_json::Value) -> Self {
+        self.details = Some(details);
+        self
+    }
+}
+
+/// Id of the request currently being handled, if called from inside [`scope_request_id`]
+pub fn request_id() -> Option<String> {
+    REQUEST_ID.try_with(|id| id.clone()).ok()
+}
+
+/// Middleware that tags every request with an id, reusing the `X-Request-Id`
+/// header sent by the client or a proxy when present
+///
+/// ## Usage
+/// ```no_run
+/// App::new().wrap_fn(api::response::scope_request_id)
+/// ```
+pub fn scope_request_id<S, B>(
+    req: ServiceRequest,
+    srv: &S,
+) -> impl Future<Output = Result<ServiceResponse<B>, actix_web::Error>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+{
+    let id = req
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(String::from)
+        .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+
+    let header = HeaderValue::from_str(&id).ok();
+    let fut = REQUEST_ID.scope(id, srv.call(req));
+
+    async move {
+        let mut res = fut.await?;
+        if let Some(header) = header {
+            res.headers_mut()
+                .insert(HeaderName::from_static(REQUEST_ID_HEADER), header);
+        }
+        Ok(res)
+    }
+}
+
+/// Answers a `GET` of a single record, `304 Not Modified` without a body when
+/// the client already holds the record at `version`
+///
+/// `If-None-Match` is checked first, `If-Modified-Since` only when the client
+/// sent no ETag and the record has a `last_modified` date.
+///
+/// ## Usage
+/// ```no_run
+/// let item = ItemBMC::get(db, &id).await?;
+/// Ok(conditional(&req, item.version, Some(item.last_modified()), &item))
+/// ```
+pub fn conditional<T: Serialize>(
+    req: &HttpRequest,
+    version: u64,
+    last_modified: Option<SystemTime>,
+    body: &T,
+) -> HttpResponse {
+    let etag = etag(version);
+    // HTTP dates are whole seconds, compare them the way the client sees them
+    let last_modified = last_modified.map(|time| {
+        let secs = time
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or_default();
+        HttpDate::from(UNIX_EPOCH + Duration::from_secs(secs))
+    });
+
+    let not_modified = match req.get_header::<IfNoneMatch>() {
+        Some(IfNoneMatch::Any) => true,
+        Some(IfNoneMatch::Items(tags)) => tags.iter().any(|tag| tag.weak_eq(&etag)),
+        None => match (req.get_header::<IfModifiedSince>(), last_modified) {
+            (Some(IfModifiedSince(since)), Some(last_modified)) => {
+                SystemTime::from(last_modified) <= SystemTime::from(since)
+            }
+            _ => false,
+        },
+    };
+
+    let mut builder = match not_modified {
+        true => HttpResponse::NotModified(),
+        false => HttpResponse::Ok(),
+    };
+
+    builder.insert_header(ETag(etag));
+    if let Some(last_modified) = last_modified {
+        builder.insert_header(LastModified(last_modified));
+    }
+
+    match not_modified {
+        true => builder.finish(),
+        false => builder.json(body),
+    }
+}
+
+impl ResponseError for Error {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Error::CtxFail | Error::AuthFail(_) => StatusCode::UNAUTHORIZED,
+            Error::Forbidden(_) => StatusCode::FORBIDDEN,
+            Error::InvalidInput(_)
+            | Error::InvalidField(..)
+            | Error::InvalidFieldValue(..)
+            | Error::InvalidFilter(_)
+            | Error::InvalidPatch(_)
+            | Error::InvalidCursor => StatusCode::BAD_REQUEST,
+            Error::StoreRecordNotFound(_) => StatusCode::NOT_FOUND,
+            Error::StoreConflict(_) | Error::VersionConflict(_) | Error::PatchTestFailed(_) => {
+                StatusCode::CONFLICT
+            }
+            Error::Validation(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            Error::PreconditionFailed(_) => StatusCode::PRECONDITION_FAILED,
+            Error::VersionRequired => StatusCode::PRECONDITION_REQUIRED,
+            Error::RateLimited(_) => StatusCode::TOO_MANY_REQUESTS,
+            Error::Surreal(err) => match err {
+                Db::RecordExists { .. } | Db::IndexExists { .. } => StatusCode::CONFLICT,
+                Db::InvalidQuery { .. } | Db::IdInvalid { .. } | Db::FieldValue { .. } => {
+                    StatusCode::BAD_REQUEST
+                }
+                _ => StatusCode::INTERNAL_SERVER_ERROR,
+            },
+            Error::Actix(err) => err.as_response_error().status_code(),
+            Error::ConfigInvalid(..)
+            | Error::MigrationFailed(_)
+            | Error::XValueNotOfType(_)
+            | Error::XPropertyNotFound(_)
+            | Error::StoreFailToCreate(_)
+            | Error::StoreNoResponse
+            | Error::Json(_)
+            | Error::Jwt(_)
+            | Error::IO(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let status = self.status_code();
+
+        // Do not leak store internals to the client, they only go to the log
+        let message = if status.is_server_error() {
+            log::error!("[{}] {}", request_id().unwrap_or_default(), self);
+            status
+                .canonical_reason()
+                .unwrap_or("Internal Server Error")
+                .to_string()
+        } else if let Error::Surreal(err) = self {
+            log::warn!("[{}] {}", request_id().unwrap_or_default(), err);
+            match err {
+                Db::RecordExists { .. } | Db::IndexExists { .. } => "Record already exists",
+                Db::FieldValue { .. } => "A field holds a value the store does not accept",
+                Db::IdInvalid { .. } => "Invalid record id",
+                _ => "Invalid query",
+            }
+            .to_string()
+        } else {
+            self.to_string()
+        };
+
+        let mut response = Response::new_error(status.as_u16(), message);
+
+        match self {
+            Error::InvalidField(_, accepted) => {
+                response = response.with_details(serde_json::json!({ "accepted": accepted }));
+            }
+            Error::Validation(violations) => {
+                response = response.with_details(serde_json::json!({ "errors": violations }));
+            }
+            Error::StoreConflict(field) => {
+                response = response.with_details(serde_json::json!({ "field": field }));
+            }
+            Error::VersionConflict(version) | Error::PreconditionFailed(version) => {
+                response = response.with_details(serde_json::json!({ "version": version }));
+            }
+            _ => (),
+        }
+
+        let mut builder = HttpResponse::build(status);
+
+        if status == StatusCode::UNAUTHORIZED {
+            builder.insert_header((header::WWW_AUTHENTICATE, "Bearer"));
+        }
+
+        if let Error::RateLimited(retry_after) = self {
+            builder.insert_header((header::RETRY_AFTER, retry_after.to_string()));
+        }
+
+        builder.json(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{body::to_bytes, test::TestRequest};
+    use serde_json::json;
+
+    use super::*;
+
+    /// Half a second past a whole second, as stored
+    fn edited() -> SystemTime {
+        UNIX_EPOCH + Duration::from_millis(1_000_500)
+    }
+
+    fn http_date(secs: u64) -> String {
+        HttpDate::from(UNIX_EPOCH + Duration::from_secs(secs)).to_string()
+    }
+
+    fn status(headers: &[(HeaderName, &str)], last_modified: Option<SystemTime>) -> StatusCode {
+        let mut req = TestRequest::default();
+        for (name, value) in headers {
+            req = req.insert_header((name.clone(), *value));
+        }
+
+        conditional(&req.to_http_request(), 3, last_modified, &json!({})).status()
+    }
+
+    #[actix_web::test]
+    async fn records_are_returned_with_their_etag_and_date() {
+        let req = TestRequest::default().to_http_request();
+
+        let res = conditional(&req, 3, Some(edited()), &json!({ "name": "x" }));
+
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers().get(header::ETAG).unwrap(), "\"3\"");
+        assert_eq!(
+            res.headers().get(header::LAST_MODIFIED).unwrap(),
+            http_date(1000).as_str()
+        );
+        assert_eq!(to_bytes(res.into_body()).await.unwrap(), r#"{"name":"x"}"#);
+    }
+
+    #[actix_web::test]
+    async fn not_modified_responses_have_no_body() {
+        let req = TestRequest::default()
+            .insert_header((header::IF_NONE_MATCH, "\"3\""))
+            .to_http_request();
+
+        let res = conditional(&req, 3, None, &json!({ "name": "x" }));
+
+        assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(res.headers().get(header::ETAG).unwrap(), "\"3\"");
+        assert!(res.headers().get(header::LAST_MODIFIED).is_none());
+        assert!(to_bytes(res.into_body()).await.unwrap().is_empty());
+    }
+
+    #[test]
+    fn if_none_match_compares_etags_weakly() {
+        let cases = [
+            ("\"3\"", StatusCode::NOT_MODIFIED),
+            ("W/\"3\"", StatusCode::NOT_MODIFIED),
+            ("\"1\", \"3\"", StatusCode::NOT_MODIFIED),
+            ("*", StatusCode::NOT_MODIFIED),
+            ("\"2\"", StatusCode::OK),
+            ("W/\"2\"", StatusCode::OK),
+        ];
+
+        for (value, expected) in cases {
+            assert_eq!(
+                status(&[(header::IF_NONE_MATCH, value)], Some(edited())),
+                expected,
+                "{}",
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn if_modified_since_compares_whole_seconds() {
+        let cases = [
+            (http_date(1000), StatusCode::NOT_MODIFIED),
+            (http_date(1001), StatusCode::NOT_MODIFIED),
+            (http_date(999), StatusCode::OK),
+        ];
+
+        for (since, expected) in cases {
+            assert_eq!(
+                status(&[(header::IF_MODIFIED_SINCE, &since)], Some(edited())),
+                expected,
+                "{}",
+                since
+            );
+        }
+    }
+
+    #[test]
+    fn if_modified_since_is_only_a_fallback() {
+        let since = http_date(1001);
+
+        // A stale ETag wins over a fresh date
+        assert_eq!(
+            status(
+                &[
+                    (header::IF_NONE_MATCH, "\"2\""),
+                    (header::IF_MODIFIED_SINCE, &since)
+                ],
+                Some(edited())
+            ),
+            StatusCode::OK
+        );
+        // Records without a date are never revalidated by one
+        assert_eq!(
+            status(&[(header::IF_MODIFIED_SINCE, &since)], None),
+            StatusCode::OK
+        );
+        // Unparsable dates are ignored
+        assert_eq!(
+            status(&[(header::IF_MODIFIED_SINCE, "yesterday")], Some(edited())),
+            StatusCode::OK
+        );
+    }
+}
@@ -1,24 +1,59 @@
 // File: src/api/user_api.rs
 use actix_web::{
-    delete, get, post, put,
-    web::{Data, Json, Path},
-    HttpResponse,
+    delete, get, patch, post, put,
+    web::{Data, Json, Path, Query},
+    HttpRequest, HttpResponse,
 };
-use serde::Deserialize;
-use serde_json::Value;
+use serde::{de::IgnoredAny, Deserialize};
 
-use crate::model::user_model::{User, UserBMC, UserPatch};
-use crate::repository::surrealdb_repo::SurrealDBRepo;
+use crate::api::patch::Patch;
+use crate::api::response::conditional;
+use crate::ctx::AuthUser;
+use crate::model::user_model::{Role, User, UserBMC, UserField};
+use crate::policy::Owned;
+use crate::prelude::*;
+use crate::repository::filter::{DateRange, Filter};
+use crate::repository::page::PageParams;
+use crate::repository::sort::SortParams;
+use crate::repository::surrealdb_repo::{Repository, SurrealDBRepo};
+use crate::repository::version::{ExpectedVersion, Versioned};
+
+/// Body of the user write endpoints
+///
+/// Private keys never leave their owner, a body still carrying one is
+/// rejected rather than having the key silently dropped.
+#[derive(Deserialize)]
+pub struct UserBody<T> {
+    #[serde(flatten)]
+    data: T,
+    #[serde(default)]
+    private_key: Option<IgnoredAny>,
+}
+
+impl<T> UserBody<T> {
+    fn data(self) -> Result<T, Error> {
+        match self.private_key {
+            Some(_) => Err(Error::InvalidInput(
+                "private_key is not accepted, keep it on the client".into(),
+            )),
+            None => Ok(self.data),
+        }
+    }
+}
 
 #[post("/users")]
-pub async fn create_user(db: Data<SurrealDBRepo>, new_user: Json<User>) -> HttpResponse {
+pub async fn create_user(
+    db: Data<SurrealDBRepo>,
+    new_user: Json<UserBody<User>>,
+) -> Result<HttpResponse, Error> {
+    let new_user = new_user.into_inner().data()?;
+
     let data = User {
         id: None,
         cid: new_user.cid.to_owned(),
         public_key: new_user.public_key.to_owned(),
-        private_key: new_user.private_key.to_owned(),
         name: new_user.name.to_owned(),
-        version: new_user.version.to_owned(),
+        version: 0,
         avatar: new_user.avatar.to_owned(),
         email: new_user.email.to_owned(),
         creation_date: new_user.creation_date.to_owned(),
@@ -26,133 +61,211 @@ pub async fn create_user(db: Data<SurrealDBRepo>, new_user: Json<User>) -> HttpR
         follow_ids: new_user.follow_ids.to_owned(),
         is_visible: new_user.is_visible.to_owned(),
         is_inactive: new_user.is_inactive.to_owned(),
+        role: Role::User,
     };
 
-    let user_detail = UserBMC::create(db, "user", data).await;
+    let user_detail = UserBMC::create(db, data).await?;
 
-    match user_detail {
-        Ok(user) => HttpResponse::Ok().json(user),
-        Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
-    }
+    Ok(HttpResponse::Ok().json(user_detail))
 }
 
 #[get("/users/{id}")]
-pub async fn get_user(db: Data<SurrealDBRepo>, path: Path<String>) -> HttpResponse {
+pub async fn get_user(
+    db: Data<SurrealDBRepo>,
+    req: HttpRequest,
+    path: Path<String>,
+) -> Result<HttpResponse, Error> {
     let id = path.into_inner();
 
     if id.is_empty() {
-        return HttpResponse::BadRequest().body("invalid ID");
+        return Err(Error::InvalidInput("invalid ID".into()));
     }
 
-    let user_detail = UserBMC::get(db, &id).await;
+    let user_detail = UserBMC::get(db, &id).await?;
 
-    match user_detail {
-        Ok(user) => HttpResponse::Ok().json(user),
-        Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
-    }
+    // Users carry no edition date, they are only revalidated by ETag
+    Ok(conditional(&req, user_detail.version, None, &user_detail))
 }
 
 #[put("/users/{id}")]
 pub async fn update_user(
     db: Data<SurrealDBRepo>,
+    auth: AuthUser,
+    req: HttpRequest,
     path: Path<String>,
-    user_patch: Json<UserPatch>,
-) -> HttpResponse {
+    body: Json<UserBody<Versioned<User>>>,
+) -> Result<HttpResponse, Error> {
     let id = path.into_inner();
 
     if id.is_empty() {
-        return HttpResponse::BadRequest().body("invalid ID");
+        return Err(Error::InvalidInput("invalid ID".into()));
+    };
+
+    let Versioned {
+        version,
+        record: user,
+    } = body.into_inner().data()?;
+    let expected = ExpectedVersion::from_request(&req, version)?;
+
+    let data = User {
+        id: None,
+        cid: user.cid.to_owned(),
+        public_key: user.public_key.to_owned(),
+        name: user.name.to_owned(),
+        version: expected.value(),
+        avatar: user.avatar.to_owned(),
+        email: user.email.to_owned(),
+        creation_date: user.creation_date.to_owned(),
+        online_state: user.online_state.to_owned(),
+        follow_ids: user.follow_ids.to_owned(),
+        is_visible: user.is_visible.to_owned(),
+        is_inactive: user.is_inactive.to_owned(),
+        role: Role::User,
     };
 
-    let data = UserPatch {
-        cid: user_patch.cid.to_owned(),
-        public_key: user_patch.public_key.to_owned(),
-        private_key: user_patch.private_key.to_owned(),
-        name: user_patch.name.to_owned(),
-        version: user_patch.version.to_owned(),
-        avatar: user_patch.avatar.to_owned(),
-        email: user_patch.email.to_owned(),
-        creation_date: user_patch.creation_date.to_owned(),
-        online_state: user_patch.online_state.to_owned(),
-        follow_ids: user_patch.follow_ids.to_owned(),
-        is_visible: user_patch.is_visible.to_owned(),
-        is_inactive: user_patch.is_inactive.to_owned(),
+    let update_result = UserBMC::replace_as(db, &auth, &id, data, expected).await?;
+
+    Ok(HttpResponse::Ok().json(update_result))
+}
+
+#[patch("/users/{id}")]
+pub async fn patch_user(
+    db: Data<SurrealDBRepo>,
+    auth: AuthUser,
+    req: HttpRequest,
+    path: Path<String>,
+    body: Json<serde_json::Value>,
+) -> Result<HttpResponse, Error> {
+    let id = path.into_inner();
+
+    if id.is_empty() {
+        return Err(Error::InvalidInput("invalid ID".into()));
     };
 
-    let update_result = UserBMC::update(db, &id, data).await;
+    let mut patch = Patch::from_request(&req, body.into_inner())?;
+    let expected = ExpectedVersion::from_request(&req, patch.version()?)?;
 
-    match update_result {
-        Ok(user) => HttpResponse::Ok().json(user),
-        Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
-    }
+    let update_result = UserBMC::patch_as(db, &auth, &id, expected, |user| {
+        let mut doc = serde_json::to_value(user)?;
+        patch.apply(&mut doc)?;
+        serde_json::from_value::<UserBody<User>>(doc)
+            .map_err(|err| Error::InvalidPatch(err.to_string()))?
+            .data()
+    })
+    .await?;
+
+    Ok(HttpResponse::Ok().json(update_result))
 }
+
 #[delete("/users/{id}")]
-pub async fn delete_user(db: Data<SurrealDBRepo>, path: Path<String>) -> HttpResponse {
+pub async fn delete_user(
+    db: Data<SurrealDBRepo>,
+    auth: AuthUser,
+    path: Path<String>,
+) -> Result<HttpResponse, Error> {
     let id = path.into_inner();
 
     if id.is_empty() {
-        return HttpResponse::BadRequest().body("invalid ID");
+        return Err(Error::InvalidInput("invalid ID".into()));
     };
 
-    let result = UserBMC::delete(db, &id).await;
+    let result = UserBMC::delete_as(db, &auth, &id).await?;
 
-    match result {
-        Ok(user) => HttpResponse::Ok().json(user),
-        Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
-    }
+    Ok(HttpResponse::Ok().json(result))
+}
+
+#[derive(Deserialize)]
+pub struct ListUsers {
+    filter: Option<String>,
 }
 
 #[get("/users")]
-pub async fn get_users(db: Data<SurrealDBRepo>) -> HttpResponse {
-    let result = UserBMC::get_all(db).await;
+pub async fn get_users(
+    db: Data<SurrealDBRepo>,
+    list_params: Query<ListUsers>,
+    range_params: Query<DateRange>,
+    sort_params: Query<SortParams>,
+    page_params: Query<PageParams>,
+) -> Result<HttpResponse, Error> {
+    let filter: Option<Filter> = match &list_params.filter {
+        Some(filter) => Some(
+            serde_json::from_str(filter).map_err(|err| Error::InvalidFilter(err.to_string()))?,
+        ),
+        None => None,
+    };
+    let filter = range_params.and::<UserField>(filter)?;
 
-    match result {
-        Ok(users) => HttpResponse::Ok().json(users),
-        Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
-    }
+    let sort = sort_params.parse::<UserField>()?;
+
+    let result = UserBMC::list(db, filter.as_ref(), sort, &page_params).await?;
+
+    Ok(HttpResponse::Ok().json(result))
 }
 
 #[derive(Deserialize)]
 pub struct SearchUsersByIds {
-    ids: Vec<String>,
+    ids: String,
 }
 
 #[get("/usersByIds")]
 pub async fn search_users_by_ids(
     db: Data<SurrealDBRepo>,
-    search_params: Json<SearchUsersByIds>,
-) -> HttpResponse {
-    let array_ids = search_params.ids.iter().map(|c| c.as_str()).collect();
-    let result = UserBMC::search_by_ids(db, array_ids).await;
-
-    match result {
-        Ok(users) => HttpResponse::Ok().json(users),
-        Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
+    search_params: Query<SearchUsersByIds>,
+    sort_params: Query<SortParams>,
+) -> Result<HttpResponse, Error> {
+    let sort = sort_params.parse::<UserField>()?;
+
+    let array_ids: Vec<&str> = search_params
+        .ids
+        .split(',')
+        .map(str::trim)
+        .filter(|id| !id.is_empty())
+        .collect();
+
+    if array_ids.is_empty() {
+        return Err(Error::InvalidInput("expected at least one id".into()));
     }
-}
 
-#[derive(Deserialize)]
-pub struct SearchUsersBy {
-    param: (String, Value),
+    let result = UserBMC::search_by_ids(db, array_ids, &sort).await?;
+
+    Ok(HttpResponse::Ok().json(result))
 }
 
 #[get("/usersBy")]
 pub async fn search_users_by(
     db: Data<SurrealDBRepo>,
-    search_params: Json<SearchUsersBy>,
-) -> HttpResponse {
-    let key = search_params.param.0.to_owned();
-    let value = search_params.param.1.to_owned();
-
-    let result = match key.as_str() {
-        "publicKey" => UserBMC::search_by_public_key(db, &value.as_str().unwrap_or("")).await,
-        "name" => UserBMC::search_by_name(db, &value.as_str().unwrap_or("")).await,
-        "isInactive" => UserBMC::search_by_is_inactive(db, value.as_bool().unwrap_or(false)).await,
-        _ => panic!("Invalid key"),
-    };
+    search_params: Query<Vec<(String, String)>>,
+    range_params: Query<DateRange>,
+    sort_params: Query<SortParams>,
+    page_params: Query<PageParams>,
+) -> Result<HttpResponse, Error> {
+    let filter = Filter::from_query::<UserField>(&search_params)?;
+    let filter = range_params.and::<UserField>(Some(filter))?;
+    let sort = sort_params.parse::<UserField>()?;
 
-    match result {
-        Ok(users) => HttpResponse::Ok().json(users),
-        Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
-    }
+    let result = UserBMC::list(db, filter.as_ref(), sort, &page_params).await?;
+
+    Ok(HttpResponse::Ok().json(result))
+}
+
+#[derive(Deserialize)]
+pub struct SearchUsers {
+    filter: Option<Filter>,
+    #[serde(flatten)]
+    sort: SortParams,
+    #[serde(flatten)]
+    page: PageParams,
+}
+
+#[post("/users/search")]
+pub async fn search_users(
+    db: Data<SurrealDBRepo>,
+    search_params: Json<SearchUsers>,
+) -> Result<HttpResponse, Error> {
+    let sort = search_params.sort.parse::<UserField>()?;
+
+    let result =
+        UserBMC::list(db, search_params.filter.as_ref(), sort, &search_params.page).await?;
+
+    Ok(HttpResponse::Ok().json(result))
 }
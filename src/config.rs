@@ -0,0 +1,200 @@
+use std::env;
+use std::net::ToSocketAddrs;
+use std::str::FromStr;
+use std::time::Duration;
+
+use jsonwebtoken::Algorithm;
+
+use crate::prelude::*;
+use crate::rate_limit::Quota;
+use crate::repository::surrealdb_repo;
+
+/// Settings read from the environment, a `.env` file is loaded first when present
+///
+/// | Variable | Default |
+/// | -------- | ------- |
+/// | `DATASTORE_URL` | `memory` |
+/// | `DATASTORE_NAMESPACE` | `test` |
+/// | `DATASTORE_DATABASE` | `test` |
+/// | `BIND_ADDRESS` | `127.0.0.1:8080` |
+/// | `WORKERS` | number of physical cores |
+/// | `LOG_LEVEL` | `info` |
+/// | `AUTO_MIGRATE` | `true` |
+/// | `JWT_ALGORITHM` | `HS256` |
+/// | `JWT_SECRET` | random, tokens do not survive a restart |
+/// | `JWT_PRIVATE_KEY_FILE` | none, required for `EdDSA` |
+/// | `JWT_PUBLIC_KEY_FILE` | none, required for `EdDSA` |
+/// | `ACCESS_TOKEN_TTL` | `900` seconds |
+/// | `REFRESH_TOKEN_TTL` | `1209600` seconds |
+/// | `RATE_LIMIT` | `120/60`, requests per seconds of a client |
+/// | `RATE_LIMIT_STRICT` | `20/60`, same for creating and searching |
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub datastore_url: String,
+    pub namespace: String,
+    pub database: String,
+    pub bind_address: String,
+    pub workers: Option<usize>,
+    pub log_level: String,
+    pub auto_migrate: bool,
+    pub jwt_algorithm: Algorithm,
+    pub jwt_secret: Option<String>,
+    pub jwt_private_key_file: Option<String>,
+    pub jwt_public_key_file: Option<String>,
+    pub access_token_ttl: u64,
+    pub refresh_token_ttl: u64,
+    pub rate_limit: Quota,
+    pub rate_limit_strict: Quota,
+}
+
+impl Config {
+    pub fn from_env() -> Result<Self, Error> {
+        dotenv::dotenv().ok();
+
+        let config = Config {
+            datastore_url: var("DATASTORE_URL")?.unwrap_or_else(|| "memory".into()),
+            namespace: var("DATASTORE_NAMESPACE")?.unwrap_or_else(|| "test".into()),
+            database: var("DATASTORE_DATABASE")?.unwrap_or_else(|| "test".into()),
+            bind_address: var("BIND_ADDRESS")?.unwrap_or_else(|| "127.0.0.1:8080".into()),
+            workers: parse("WORKERS")?,
+            log_level: var("LOG_LEVEL")?.unwrap_or_else(|| "info".into()),
+            auto_migrate: parse("AUTO_MIGRATE")?.unwrap_or(true),
+            jwt_algorithm: parse("JWT_ALGORITHM")?.unwrap_or(Algorithm::HS256),
+            jwt_secret: var("JWT_SECRET")?,
+            jwt_private_key_file: var("JWT_PRIVATE_KEY_FILE")?,
+            jwt_public_key_file: var("JWT_PUBLIC_KEY_FILE")?,
+            access_token_ttl: parse("ACCESS_TOKEN_TTL")?.unwrap_or(15 * 60),
+            refresh_token_ttl: parse("REFRESH_TOKEN_TTL")?.unwrap_or(14 * 24 * 60 * 60),
+            rate_limit: parse("RATE_LIMIT")?.unwrap_or(Quota {
+                requests: 120,
+                period: Duration::from_secs(60),
+            }),
+            rate_limit_strict: parse("RATE_LIMIT_STRICT")?.unwrap_or(Quota {
+                requests: 20,
+                period: Duration::from_secs(60),
+            }),
+        };
+
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<(), Error> {
+        if let Err(cause) = surrealdb_repo::check_url(&self.datastore_url) {
+            return Err(invalid("DATASTORE_URL", &cause));
+        }
+
+        if self.namespace.is_empty() {
+            return Err(invalid("DATASTORE_NAMESPACE", "must not be empty"));
+        }
+
+        if self.database.is_empty() {
+            return Err(invalid("DATASTORE_DATABASE", "must not be empty"));
+        }
+
+        if let Err(err) = self.bind_address.to_socket_addrs() {
+            return Err(invalid("BIND_ADDRESS", &err.to_string()));
+        }
+
+        if self.workers == Some(0) {
+            return Err(invalid("WORKERS", "must be at least 1"));
+        }
+
+        match self.jwt_algorithm {
+            Algorithm::HS256 => {
+                if self
+                    .jwt_secret
+                    .as_ref()
+                    .is_some_and(|secret| secret.len() < 32)
+                {
+                    return Err(invalid("JWT_SECRET", "must be at least 32 bytes long"));
+                }
+            }
+            Algorithm::EdDSA => {
+                if self.jwt_private_key_file.is_none() {
+                    return Err(invalid("JWT_PRIVATE_KEY_FILE", "required for EdDSA"));
+                }
+
+                if self.jwt_public_key_file.is_none() {
+                    return Err(invalid("JWT_PUBLIC_KEY_FILE", "required for EdDSA"));
+                }
+            }
+            _ => return Err(invalid("JWT_ALGORITHM", "expected HS256 or EdDSA")),
+        }
+
+        if self.access_token_ttl == 0 {
+            return Err(invalid("ACCESS_TOKEN_TTL", "must be at least 1 second"));
+        }
+
+        if self.refresh_token_ttl == 0 {
+            return Err(invalid("REFRESH_TOKEN_TTL", "must be at least 1 second"));
+        }
+
+        if log::LevelFilter::from_str(&self.log_level).is_err() {
+            return Err(invalid(
+                "LOG_LEVEL",
+                "expected one of off, error, warn, info, debug, trace",
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+impl Config {
+    /// Defaults with a fixed `HS256` secret, for tests
+    pub fn fixture() -> Self {
+        Config {
+            datastore_url: "memory".into(),
+            namespace: "test".into(),
+            database: "test".into(),
+            bind_address: "127.0.0.1:8080".into(),
+            workers: None,
+            log_level: "info".into(),
+            auto_migrate: true,
+            jwt_algorithm: Algorithm::HS256,
+            jwt_secret: Some("0123456789abcdef0123456789abcdef".into()),
+            jwt_private_key_file: None,
+            jwt_public_key_file: None,
+            access_token_ttl: 15 * 60,
+            refresh_token_ttl: 14 * 24 * 60 * 60,
+            rate_limit: Quota {
+                requests: 120,
+                period: Duration::from_secs(60),
+            },
+            rate_limit_strict: Quota {
+                requests: 20,
+                period: Duration::from_secs(60),
+            },
+        }
+    }
+}
+
+fn invalid(key: &'static str, cause: &str) -> Error {
+    Error::ConfigInvalid(key, cause.to_string())
+}
+
+/// Reads `key`, treating unset and empty values the same
+fn var(key: &'static str) -> Result<Option<String>, Error> {
+    match env::var(key) {
+        Ok(value) if value.trim().is_empty() => Ok(None),
+        Ok(value) => Ok(Some(value.trim().to_string())),
+        Err(env::VarError::NotPresent) => Ok(None),
+        Err(env::VarError::NotUnicode(_)) => Err(invalid(key, "not valid unicode")),
+    }
+}
+
+fn parse<T: FromStr>(key: &'static str) -> Result<Option<T>, Error>
+where
+    T::Err: std::fmt::Display,
+{
+    match var(key)? {
+        Some(value) => value
+            .parse()
+            .map(Some)
+            .map_err(|err: T::Err| invalid(key, &err.to_string())),
+        None => Ok(None),
+    }
+}
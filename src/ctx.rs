@@ -0,0 +1,325 @@
+use actix_web::{
+    body::EitherBody,
+    dev::{forward_ready, Payload, Service, ServiceRequest, ServiceResponse, Transform},
+    http::header,
+    web::Data,
+    FromRequest, HttpMessage, HttpRequest,
+};
+use futures::future::LocalBoxFuture;
+use std::future::{ready, Ready};
+use std::rc::Rc;
+
+use crate::jwt::Jwt;
+use crate::model::api_key_model::{ApiKey, ApiKeyBMC, Scope};
+use crate::model::user_model::{Role, UserBMC};
+use crate::prelude::*;
+use crate::repository::surrealdb_repo::{Repository, SurrealDBRepo};
+
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+/// Signed in user, read from the access token of the
+/// `Authorization: Bearer <token>` header, or the service calling with the
+/// API key of the `X-API-Key` header
+///
+/// Handlers taking an `AuthUser` answer `401` to requests without a valid,
+/// unexpired token or an unrevoked key. Only API keys involve a store
+/// lookup, the result is kept for the rest of the request.
+///
+/// ## Usage
+/// ```no_run
+/// #[delete("/items/{id}")]
+/// pub async fn delete_item(auth: AuthUser, ...) -> Result<HttpResponse, Error>
+/// ```
+#[derive(Debug, Clone)]
+pub struct AuthUser {
+    user_id: String,
+    role: Role,
+    /// Scopes of the API key, `None` when signed in with an access token
+    scopes: Option<Vec<Scope>>,
+}
+
+impl AuthUser {
+    pub fn user_id(&self) -> &str {
+        &self.user_id
+    }
+
+    pub fn role(&self) -> Role {
+        self.role
+    }
+
+    /// Keys act for their user with the role the user holds now when they
+    /// have the `admin` scope, and as a plain user otherwise
+    fn from_api_key(api_key: ApiKey, owner_role: Role) -> Self {
+        let role = match api_key.scopes.contains(&Scope::Admin) {
+            true => owner_role,
+            false => Role::User,
+        };
+
+        AuthUser {
+            user_id: api_key.user_id,
+            role,
+            scopes: Some(api_key.scopes),
+        }
+    }
+
+    /// Fails with a `403` unless the user holds at least `role`
+    pub fn require(&self, role: Role) -> Result<(), Error> {
+        if self.role < role {
+            return Err(Error::Forbidden(format!(
+                "requires the {} role",
+                role.as_str()
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Fails with a `403` when called with an API key lacking `scope`, users
+    /// signed in with an access token hold every scope
+    pub fn require_scope(&self, scope: Scope) -> Result<(), Error> {
+        match &self.scopes {
+            Some(scopes) if !scopes.contains(&scope) && !scopes.contains(&Scope::Admin) => Err(
+                Error::Forbidden(format!("API key lacks the '{}' scope", scope.as_str())),
+            ),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+impl AuthUser {
+    /// Caller acting for `user_id`, with the `scopes` of its API key or every
+    /// scope when `None`, for tests
+    pub fn fixture(user_id: &str, role: Role, scopes: Option<Vec<Scope>>) -> Self {
+        AuthUser {
+            user_id: user_id.into(),
+            role,
+            scopes,
+        }
+    }
+}
+
+impl FromRequest for AuthUser {
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        if let Some(auth) = req.extensions().get::<AuthUser>() {
+            return Box::pin(ready(Ok(auth.clone())));
+        }
+
+        let req = req.clone();
+
+        Box::pin(async move {
+            let auth = match header_value(&req, API_KEY_HEADER) {
+                Some(key) => {
+                    let db = req
+                        .app_data::<Data<SurrealDBRepo>>()
+                        .ok_or(Error::CtxFail)?;
+
+                    // Store failures answer 401 as well, optional routes
+                    // and required ones treat the key the same
+                    let lookup_failed = |err: Error| {
+                        log::warn!("API key lookup failed: {}", err);
+                        Error::AuthFail("the API key could not be checked")
+                    };
+
+                    let api_key = ApiKeyBMC::authenticate(db.clone(), key)
+                        .await
+                        .map_err(lookup_failed)?
+                        .ok_or(Error::AuthFail("unknown or revoked API key"))?;
+
+                    // Keys die with their user
+                    let owner = match UserBMC::get(db.clone(), &api_key.user_id).await {
+                        Err(Error::StoreRecordNotFound(_)) => {
+                            return Err(Error::AuthFail("the user of the API key no longer exists"))
+                        }
+                        owner => owner.map_err(lookup_failed)?,
+                    };
+
+                    AuthUser::from_api_key(api_key, owner.role)
+                }
+                None => {
+                    let jwt = req.app_data::<Data<Jwt>>().ok_or(Error::CtxFail)?;
+                    let token = header_value(&req, header::AUTHORIZATION.as_str())
+                        .and_then(|value| value.strip_prefix("Bearer "))
+                        .map(str::trim)
+                        .filter(|token| !token.is_empty())
+                        .ok_or(Error::CtxFail)?;
+
+                    let claims = jwt.validate(token)?;
+
+                    AuthUser {
+                        user_id: claims.sub,
+                        role: claims.role,
+                        scopes: None,
+                    }
+                }
+            };
+
+            req.extensions_mut().insert(auth.clone());
+
+            Ok(auth)
+        })
+    }
+}
+
+fn header_value<'a>(req: &'a HttpRequest, name: &str) -> Option<&'a str> {
+    req.headers()
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+}
+
+/// Middleware restricting a scope to callers holding at least the role
+///
+/// ## Usage
+/// ```no_run
+/// web::scope("/admin")
+///     .wrap(RequireRole(Role::Admin))
+///     .service(admin_api::get_inactive_users)
+/// ```
+pub struct RequireRole(pub Role);
+
+impl<S, B> Transform<S, ServiceRequest> for RequireRole
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = actix_web::Error;
+    type Transform = RequireRoleMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequireRoleMiddleware {
+            service: Rc::new(service),
+            role: self.0,
+        }))
+    }
+}
+
+pub struct RequireRoleMiddleware<S> {
+    service: Rc<S>,
+    role: Role,
+}
+
+impl<S, B> Service<ServiceRequest> for RequireRoleMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+        let role = self.role;
+
+        Box::pin(async move {
+            // Rejections are answered as responses rather than errors so they
+            // still go through the request id and rate limit middlewares
+            let allowed = AuthUser::from_request(req.request(), &mut Payload::None)
+                .await
+                .and_then(|auth| auth.require(role));
+
+            match allowed {
+                Ok(()) => Ok(service.call(req).await?.map_into_left_body()),
+                Err(err) => Ok(req.error_response(err).map_into_right_body()),
+            }
+        })
+    }
+}
+
+#[cfg(all(test, feature = "kv-mem"))]
+mod tests {
+    use actix_web::test::TestRequest;
+
+    use super::*;
+    use crate::model::user_model::User;
+
+    /// Store holding a user with `role` and a key of theirs with `scopes`
+    async fn db_with_key(role: Role, scopes: &[Scope]) -> (Data<SurrealDBRepo>, String, String) {
+        let db = Data::new(SurrealDBRepo::in_memory().await.unwrap());
+        let user = UserBMC::create(db.clone(), User::fixture("owner"))
+            .await
+            .unwrap();
+        let user_id = user.id.unwrap();
+        UserBMC::set_role(db.clone(), &user_id, role).await.unwrap();
+        let key = ApiKeyBMC::create(db.clone(), &user_id, "import", scopes)
+            .await
+            .unwrap()
+            .key;
+        (db, user_id, key)
+    }
+
+    async fn extract(db: &Data<SurrealDBRepo>, key: &str) -> Result<AuthUser, Error> {
+        let req = TestRequest::default()
+            .app_data(db.clone())
+            .insert_header((API_KEY_HEADER, key))
+            .to_http_request();
+        AuthUser::extract(&req).await
+    }
+
+    #[actix_web::test]
+    async fn api_keys_act_for_their_user_within_their_scopes() {
+        let (db, user_id, key) = db_with_key(Role::User, &[Scope::ReadItems]).await;
+
+        let auth = extract(&db, &key).await.unwrap();
+
+        assert_eq!(auth.user_id(), user_id);
+        assert_eq!(auth.role(), Role::User);
+        assert!(auth.require_scope(Scope::ReadItems).is_ok());
+        assert!(matches!(
+            auth.require_scope(Scope::WriteItems),
+            Err(Error::Forbidden(_))
+        ));
+    }
+
+    #[actix_web::test]
+    async fn api_keys_hold_the_role_of_their_user_only_with_the_admin_scope() {
+        let (db, user_id, scoped) = db_with_key(Role::Admin, &[Scope::WriteItems]).await;
+        let admin = ApiKeyBMC::create(db.clone(), &user_id, "admin", &[Scope::Admin])
+            .await
+            .unwrap()
+            .key;
+
+        let scoped = extract(&db, &scoped).await.unwrap();
+        let admin = extract(&db, &admin).await.unwrap();
+
+        assert_eq!(scoped.role(), Role::User);
+        assert!(scoped.require(Role::Admin).is_err());
+        assert_eq!(admin.role(), Role::Admin);
+        assert!(admin.require_scope(Scope::WriteItems).is_ok());
+    }
+
+    #[actix_web::test]
+    async fn unknown_and_revoked_api_keys_are_rejected() {
+        let (db, _, key) = db_with_key(Role::User, &[Scope::ReadItems]).await;
+        let id = ApiKeyBMC::list(db.clone()).await.unwrap().remove(0).id;
+        ApiKeyBMC::revoke(db.clone(), &id).await.unwrap();
+
+        for key in ["nope", key.as_str()] {
+            assert!(matches!(
+                extract(&db, key).await,
+                Err(Error::AuthFail("unknown or revoked API key"))
+            ));
+        }
+    }
+
+    #[actix_web::test]
+    async fn api_keys_of_deleted_users_are_rejected() {
+        let (db, user_id, key) = db_with_key(Role::User, &[Scope::ReadItems]).await;
+        UserBMC::delete(db.clone(), &user_id).await.unwrap();
+
+        assert!(matches!(
+            extract(&db, &key).await,
+            Err(Error::AuthFail("the user of the API key no longer exists"))
+        ));
+    }
+}
@@ -1,12 +1,59 @@
 // https://github.com/rust-awesome-app/template-app-base/blob/main/src-tauri/src/error.rs
 
+use crate::validation::Violation;
+
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
-    #[error("Fail to get Ctx")]
+    #[error("Missing bearer token in the Authorization header or API key in the X-API-Key header")]
     CtxFail,
 
-    #[error("Fetching from Untis failed")]
-    UntisError,
+    #[error("Authentication failed: {0}")]
+    AuthFail(&'static str),
+
+    #[error("Forbidden: {0}")]
+    Forbidden(String),
+
+    #[error("Invalid input: {0}")]
+    InvalidInput(String),
+
+    #[error("Unknown field '{0}', expected one of: {}", .1.join(", "))]
+    InvalidField(String, Vec<&'static str>),
+
+    #[error("Field '{0}' expects {1}")]
+    InvalidFieldValue(&'static str, &'static str),
+
+    #[error("Validation failed for {}", .0.iter().map(|v| v.field).collect::<Vec<_>>().join(", "))]
+    Validation(Vec<Violation>),
+
+    #[error("Invalid filter: {0}")]
+    InvalidFilter(String),
+
+    #[error("Invalid patch: {0}")]
+    InvalidPatch(String),
+
+    #[error("Patch test failed at '{0}'")]
+    PatchTestFailed(String),
+
+    #[error("Invalid or expired cursor")]
+    InvalidCursor,
+
+    #[error("Version conflict: the record is at version {0}")]
+    VersionConflict(u64),
+
+    #[error("Precondition failed: the record is at version {0}")]
+    PreconditionFailed(u64),
+
+    #[error("Precondition required: send the expected version as 'version' in the body or in an If-Match header")]
+    VersionRequired,
+
+    #[error("Too many requests, retry in {0} seconds")]
+    RateLimited(u64),
+
+    #[error("Invalid configuration for '{0}': {1}")]
+    ConfigInvalid(&'static str, String),
+
+    #[error("Migration failed: {0}")]
+    MigrationFailed(String),
 
     #[error("Value not of type '{0}'")]
     XValueNotOfType(&'static str),
@@ -17,14 +64,26 @@ pub enum Error {
     #[error("Fail to create. Cause: {0}")]
     StoreFailToCreate(String),
 
+    #[error("Store did not return a response")]
+    StoreNoResponse,
+
+    #[error("Record '{0}' not found")]
+    StoreRecordNotFound(String),
+
+    #[error("Value of '{0}' is already taken")]
+    StoreConflict(String),
+
     #[error(transparent)]
-    Surreal(#[from] surrealdb::Error),
+    Surreal(#[from] surrealdb::error::Db),
 
     #[error(transparent)]
     IO(#[from] std::io::Error),
 
     #[error(transparent)]
-    JSON(#[from] serde_json::Error),
+    Json(#[from] serde_json::Error),
+
+    #[error(transparent)]
+    Jwt(#[from] jsonwebtoken::errors::Error),
 
     #[error(transparent)]
     Actix(#[from] actix_web::Error),
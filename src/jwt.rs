@@ -0,0 +1,213 @@
+use jsonwebtoken::{
+    decode, encode, errors::ErrorKind, Algorithm, DecodingKey, EncodingKey, Header, Validation,
+};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::config::Config;
+use crate::model::user_model::Role;
+use crate::prelude::*;
+
+/// Claims of an access token, `sub` is the id of the signed in user
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Claims {
+    pub sub: String,
+    pub role: Role,
+    pub iat: u64,
+    pub exp: u64,
+}
+
+/// Signs and validates the stateless access tokens
+pub struct Jwt {
+    header: Header,
+    encoding: EncodingKey,
+    decoding: DecodingKey,
+    validation: Validation,
+    ttl: u64,
+}
+
+impl Jwt {
+    /// Loads the secret or the Ed25519 key pair picked by `JWT_ALGORITHM`
+    pub fn from_config(config: &Config) -> Result<Self, Error> {
+        let (encoding, decoding) = match config.jwt_algorithm {
+            Algorithm::EdDSA => {
+                let private_key = read_key("JWT_PRIVATE_KEY_FILE", &config.jwt_private_key_file)?;
+                let public_key = read_key("JWT_PUBLIC_KEY_FILE", &config.jwt_public_key_file)?;
+
+                (
+                    EncodingKey::from_ed_pem(&private_key).map_err(|err| {
+                        Error::ConfigInvalid("JWT_PRIVATE_KEY_FILE", err.to_string())
+                    })?,
+                    DecodingKey::from_ed_pem(&public_key).map_err(|err| {
+                        Error::ConfigInvalid("JWT_PUBLIC_KEY_FILE", err.to_string())
+                    })?,
+                )
+            }
+            _ => {
+                let secret = match &config.jwt_secret {
+                    Some(secret) => secret.as_bytes().to_vec(),
+                    None => {
+                        log::warn!("JWT_SECRET is not set, tokens will not survive a restart");
+                        rand::random::<[u8; 32]>().to_vec()
+                    }
+                };
+
+                (
+                    EncodingKey::from_secret(&secret),
+                    DecodingKey::from_secret(&secret),
+                )
+            }
+        };
+
+        let mut validation = Validation::new(config.jwt_algorithm);
+        validation.leeway = 0;
+
+        Ok(Jwt {
+            header: Header::new(config.jwt_algorithm),
+            encoding,
+            decoding,
+            validation,
+            ttl: config.access_token_ttl,
+        })
+    }
+
+    /// Lifetime of the issued tokens, in seconds
+    pub fn ttl(&self) -> u64 {
+        self.ttl
+    }
+
+    pub fn issue(&self, user_id: &str, role: Role) -> Result<String, Error> {
+        let iat = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |now| now.as_secs());
+
+        let claims = Claims {
+            sub: user_id.to_string(),
+            role,
+            iat,
+            exp: iat + self.ttl,
+        };
+
+        Ok(encode(&self.header, &claims, &self.encoding)?)
+    }
+
+    pub fn validate(&self, token: &str) -> Result<Claims, Error> {
+        decode::<Claims>(token, &self.decoding, &self.validation)
+            .map(|data| data.claims)
+            .map_err(|err| match err.kind() {
+                ErrorKind::ExpiredSignature => Error::AuthFail("access token expired"),
+                _ => Error::AuthFail("invalid access token"),
+            })
+    }
+}
+
+fn read_key(key: &'static str, path: &Option<String>) -> Result<Vec<u8>, Error> {
+    let path = path.as_deref().unwrap_or_default();
+
+    fs::read(path).map_err(|err| Error::ConfigInvalid(key, format!("{}: {}", path, err)))
+}
+
+#[cfg(test)]
+mod tests {
+    use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+
+    use super::*;
+
+    fn jwt(secret: &str) -> Jwt {
+        Jwt::from_config(&Config {
+            jwt_secret: Some(secret.into()),
+            ..Config::fixture()
+        })
+        .unwrap()
+    }
+
+    fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+
+    fn sign(jwt: &Jwt, header: &Header, claims: &Claims) -> String {
+        encode(header, claims, &jwt.encoding).unwrap()
+    }
+
+    #[test]
+    fn issued_tokens_validate() {
+        let jwt = jwt("0123456789abcdef0123456789abcdef");
+
+        let claims = jwt
+            .validate(&jwt.issue("user1", Role::Moderator).unwrap())
+            .unwrap();
+
+        assert_eq!(claims.sub, "user1");
+        assert_eq!(claims.role, Role::Moderator);
+        assert_eq!(claims.exp - claims.iat, jwt.ttl());
+    }
+
+    #[test]
+    fn expired_tokens_are_rejected() {
+        let jwt = jwt("0123456789abcdef0123456789abcdef");
+        let claims = Claims {
+            sub: "user1".into(),
+            role: Role::User,
+            iat: now() - 20,
+            exp: now() - 10,
+        };
+
+        let token = sign(&jwt, &jwt.header, &claims);
+
+        assert!(matches!(
+            jwt.validate(&token),
+            Err(Error::AuthFail("access token expired"))
+        ));
+    }
+
+    #[test]
+    fn tampered_tokens_are_rejected() {
+        let jwt = jwt("0123456789abcdef0123456789abcdef");
+        let token = jwt.issue("user1", Role::User).unwrap();
+
+        // Promote the user without signing the new claims
+        let parts: Vec<&str> = token.split('.').collect();
+        let claims = String::from_utf8(URL_SAFE_NO_PAD.decode(parts[1]).unwrap()).unwrap();
+        let promoted = URL_SAFE_NO_PAD.encode(claims.replace("\"user\"", "\"admin\""));
+        let tampered = [parts[0], &promoted, parts[2]].join(".");
+
+        assert_ne!(tampered, token);
+        assert!(matches!(
+            jwt.validate(&tampered),
+            Err(Error::AuthFail("invalid access token"))
+        ));
+    }
+
+    #[test]
+    fn tokens_of_another_key_or_algorithm_are_rejected() {
+        let jwt = jwt("0123456789abcdef0123456789abcdef");
+        let other = self::jwt("fedcba9876543210fedcba9876543210");
+        let claims = Claims {
+            sub: "user1".into(),
+            role: Role::User,
+            iat: now(),
+            exp: now() + 60,
+        };
+
+        let tokens = [
+            other.issue("user1", Role::User).unwrap(),
+            sign(&jwt, &Header::new(Algorithm::HS512), &claims),
+            "not.a.token".to_string(),
+        ];
+
+        for token in tokens {
+            assert!(
+                matches!(
+                    jwt.validate(&token),
+                    Err(Error::AuthFail("invalid access token"))
+                ),
+                "{}",
+                token
+            );
+        }
+    }
+}
@@ -2,19 +2,82 @@ use actix_web::{guard, middleware::Logger, web, App, HttpResponse, HttpServer};
 use env_logger::Env;
 
 mod api;
+mod config;
+mod ctx;
 mod error;
+mod jwt;
 mod model;
+mod policy;
 mod prelude;
+mod rate_limit;
 mod repository;
 mod utils;
+mod validation;
 
+use config::Config;
+use jwt::Jwt;
+use model::user_model::{Role, UserBMC};
+use prelude::*;
+use repository::migration;
 use repository::surrealdb_repo::SurrealDBRepo;
 
+/// What to do on startup, the server is started when no command is given
+enum Command {
+    Serve,
+    Migrate,
+    SetRole(String, Role),
+}
+
+impl Command {
+    fn from_args() -> Result<Self, String> {
+        let args: Vec<String> = std::env::args().skip(1).collect();
+
+        match args.iter().map(String::as_str).collect::<Vec<&str>>()[..] {
+            [] => Ok(Command::Serve),
+            ["migrate"] => Ok(Command::Migrate),
+            ["role", id, role] => role
+                .parse()
+                .map(|role| Command::SetRole(id.to_string(), role))
+                .map_err(|e: Error| e.to_string()),
+            _ => Err("Usage: actix [migrate | role <user-id> <user|moderator|admin>]".into()),
+        }
+    }
+}
+
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    let surreal = match SurrealDBRepo::init().await {
+    let command = match Command::from_args() {
+        Ok(command) => command,
+        Err(e) => {
+            println!("🔥 {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let config = match Config::from_env() {
+        Ok(config) => config,
+        Err(e) => {
+            println!("🔥 {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    env_logger::init_from_env(Env::default().default_filter_or(&config.log_level));
+
+    let jwt = match Jwt::from_config(&config) {
+        Ok(jwt) => jwt,
+        Err(e) => {
+            println!("🔥 {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let surreal = match SurrealDBRepo::init(&config).await {
         Ok(surreal) => {
             println!("✅ Connection to the database is successful!");
+            if config.datastore_url == "memory" {
+                println!("⚠️  DATASTORE_URL is memory, every record is lost when the server stops");
+            }
             surreal
         }
         Err(e) => {
@@ -23,26 +86,59 @@ async fn main() -> std::io::Result<()> {
         }
     };
 
+    if config.auto_migrate || !matches!(command, Command::Serve) {
+        match migration::migrate(&surreal).await {
+            Ok(applied) => println!("✅ Applied {} migration(s)", applied.len()),
+            Err(e) => {
+                println!("🔥 {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+
     let db_data = web::Data::new(surreal);
 
-    env_logger::init_from_env(Env::default().default_filter_or("info"));
+    match command {
+        Command::Serve => (),
+        Command::Migrate => return Ok(()),
+        Command::SetRole(id, role) => {
+            match UserBMC::set_role(db_data, &id, role).await {
+                Ok(user) => println!("✅ User '{}' is now {}", id, user.role.as_str()),
+                Err(e) => {
+                    println!("🔥 {}", e);
+                    std::process::exit(1);
+                }
+            }
+            return Ok(());
+        }
+    }
+    let config_data = web::Data::new(config.clone());
+    let jwt_data = web::Data::new(jwt);
+    let rate_limiter = api::rate_limiter(&config);
 
-    HttpServer::new(move || {
+    let mut server = HttpServer::new(move || {
         App::new()
             .app_data(db_data.clone())
+            .app_data(config_data.clone())
+            .app_data(jwt_data.clone())
             .configure(api::config)
             .service(
                 web::resource("/api").route(
                     web::route()
                         .guard(guard::Get())
                         .guard(guard::Header("content-type", "application/json"))
-                        .to(|| HttpResponse::Ok()),
+                        .to(HttpResponse::Ok),
                 ),
             )
+            .wrap(rate_limiter.clone())
+            .wrap_fn(api::response::scope_request_id)
             .wrap(Logger::default())
             .wrap(Logger::new("%a %{User-Agent}i"))
-    })
-    .bind(("127.0.0.1", 8080))?
-    .run()
-    .await
+    });
+
+    if let Some(workers) = config.workers {
+        server = server.workers(workers);
+    }
+
+    server.bind(&config.bind_address)?.run().await
 }
@@ -0,0 +1,234 @@
+use actix_web::web::Data;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::str::FromStr;
+use surrealdb::sql::{Datetime, Object, Thing, Value};
+
+use crate::prelude::*;
+use crate::repository::surrealdb_repo::SurrealDBRepo;
+use crate::utils::macros::map;
+use crate::utils::x_take::{XTakeImpl, XTakeVal};
+
+/// Permission granted to an API key, `admin` is granted everything
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Scope {
+    #[serde(rename = "read:items")]
+    ReadItems,
+    #[serde(rename = "write:items")]
+    WriteItems,
+    #[serde(rename = "admin")]
+    Admin,
+}
+
+impl Scope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::ReadItems => "read:items",
+            Scope::WriteItems => "write:items",
+            Scope::Admin => "admin",
+        }
+    }
+}
+
+impl FromStr for Scope {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Scope, Error> {
+        match s {
+            "read:items" => Ok(Scope::ReadItems),
+            "write:items" => Ok(Scope::WriteItems),
+            "admin" => Ok(Scope::Admin),
+            _ => Err(Error::InvalidFieldValue(
+                "scopes",
+                "scopes among read:items, write:items, admin",
+            )),
+        }
+    }
+}
+
+/// Key a service calls the API with instead of a user, acting for `user_id`
+/// within its `scopes`
+#[derive(Debug, Serialize)]
+pub struct ApiKey {
+    pub id: String,
+    pub name: String,
+    pub user_id: String,
+    pub scopes: Vec<Scope>,
+    pub created_at: String,
+    pub last_used_at: Option<String>,
+    pub revoked_at: Option<String>,
+}
+
+impl TryFrom<Object> for ApiKey {
+    type Error = Error;
+    fn try_from(mut val: Object) -> Result<ApiKey, Error> {
+        let mut datetime = |k: &str| -> Result<Option<String>, Error> {
+            let datetime: Option<Datetime> = val.x_take_impl(k)?;
+            Ok(datetime.map(|datetime| datetime.to_raw()))
+        };
+
+        let created_at =
+            datetime("createdAt")?.ok_or(Error::XPropertyNotFound("createdAt".into()))?;
+        let last_used_at = datetime("lastUsedAt")?;
+        let revoked_at = datetime("revokedAt")?;
+
+        Ok(ApiKey {
+            id: val.x_take_val::<Thing>("id")?.id.to_raw(),
+            name: val.x_take_val("name")?,
+            user_id: val.x_take_val("userId")?,
+            scopes: val
+                .x_take_val::<Vec<String>>("scopes")?
+                .iter()
+                .map(|scope| scope.parse())
+                .collect::<Result<Vec<Scope>, Error>>()?,
+            created_at,
+            last_used_at,
+            revoked_at,
+        })
+    }
+}
+
+/// Freshly created API key, `key` is only ever returned here
+#[derive(Debug, Serialize)]
+pub struct NewApiKey {
+    #[serde(flatten)]
+    pub api_key: ApiKey,
+    pub key: String,
+}
+
+pub struct ApiKeyBMC;
+
+impl ApiKeyBMC {
+    /// Stores a new key acting for `user_id`, only a hash of the key is stored
+    pub async fn create(
+        db: Data<SurrealDBRepo>,
+        user_id: &str,
+        name: &str,
+        scopes: &[Scope],
+    ) -> Result<NewApiKey, Error> {
+        let sql = "CREATE $th SET name = $name, userId = $userId, scopes = $scopes, \
+                   createdAt = time::now() RETURN AFTER";
+
+        let key = URL_SAFE_NO_PAD.encode(rand::random::<[u8; 32]>());
+
+        let scopes: Vec<Value> = scopes.iter().map(|scope| scope.as_str().into()).collect();
+
+        let vars = map![
+            "th".into() => Self::thing_from_key(&key).into(),
+            "name".into() => name.into(),
+            "userId".into() => user_id.into(),
+            "scopes".into() => scopes.into()];
+
+        let result = db.execute(sql, Some(vars), true).await?;
+        let api_key = Object::try_from(W(result.first()))?.try_into()?;
+
+        Ok(NewApiKey { api_key, key })
+    }
+
+    pub async fn get(db: Data<SurrealDBRepo>, id: &str) -> Result<ApiKey, Error> {
+        let thing = Thing::from(("api_key", id));
+
+        let sql = "SELECT * FROM $th";
+
+        let vars = map!["th".into() => thing.clone().into()];
+
+        let result = db.execute(sql, Some(vars), true).await?;
+
+        match result.first() {
+            Value::None => Err(Error::StoreRecordNotFound(thing.to_string())),
+            value => Object::try_from(W(value))?.try_into(),
+        }
+    }
+
+    /// Every key, revoked ones included, newest first
+    pub async fn list(db: Data<SurrealDBRepo>) -> Result<Vec<ApiKey>, Error> {
+        db.execute_all("SELECT * FROM api_key ORDER BY createdAt DESC", None)
+            .await
+    }
+
+    /// Revokes the key `id`, revoking a key twice keeps the first date
+    pub async fn revoke(db: Data<SurrealDBRepo>, id: &str) -> Result<ApiKey, Error> {
+        Self::get(db.clone(), id).await?;
+
+        let sql = "UPDATE $th SET revokedAt = revokedAt OR time::now() RETURN AFTER";
+
+        let vars = map!["th".into() => Thing::from(("api_key", id)).into()];
+
+        let result = db.execute(sql, Some(vars), true).await?;
+
+        Object::try_from(W(result.first()))?.try_into()
+    }
+
+    /// Looks up the unrevoked key `key` and records its use in the same
+    /// statement, `None` when it does not exist or has been revoked
+    pub async fn authenticate(db: Data<SurrealDBRepo>, key: &str) -> Result<Option<ApiKey>, Error> {
+        // `UPDATE` on a missing record would create it, every stored key has
+        // a `createdAt`
+        let sql = "UPDATE $th SET lastUsedAt = time::now() \
+                   WHERE revokedAt = NONE AND createdAt != NONE RETURN AFTER";
+
+        let vars = map!["th".into() => Self::thing_from_key(key).into()];
+
+        let result = db.execute(sql, Some(vars), true).await?;
+
+        match result.first() {
+            Value::None => Ok(None),
+            value => Ok(Some(Object::try_from(W(value))?.try_into()?)),
+        }
+    }
+
+    fn thing_from_key(key: &str) -> Thing {
+        Thing::from(("api_key", hex::encode(Sha256::digest(key)).as_str()))
+    }
+}
+
+#[cfg(all(test, feature = "kv-mem"))]
+mod tests {
+    use super::*;
+
+    async fn db_with_key() -> (Data<SurrealDBRepo>, NewApiKey) {
+        let db = Data::new(SurrealDBRepo::in_memory().await.unwrap());
+        let key = ApiKeyBMC::create(db.clone(), "owner", "import", &[Scope::WriteItems])
+            .await
+            .unwrap();
+        (db, key)
+    }
+
+    #[actix_web::test]
+    async fn authenticate_records_the_use_of_a_key() {
+        let (db, new_key) = db_with_key().await;
+
+        let api_key = ApiKeyBMC::authenticate(db.clone(), &new_key.key)
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(api_key.id, new_key.api_key.id);
+        assert_eq!(api_key.user_id, "owner");
+        assert_eq!(api_key.scopes, [Scope::WriteItems]);
+        assert!(new_key.api_key.last_used_at.is_none());
+        assert!(api_key.last_used_at.is_some());
+    }
+
+    #[actix_web::test]
+    async fn authenticate_ignores_unknown_keys_without_creating_them() {
+        let (db, _) = db_with_key().await;
+
+        let api_key = ApiKeyBMC::authenticate(db.clone(), "nope").await.unwrap();
+
+        assert!(api_key.is_none());
+        assert_eq!(ApiKeyBMC::list(db).await.unwrap().len(), 1);
+    }
+
+    #[actix_web::test]
+    async fn authenticate_ignores_revoked_keys() {
+        let (db, new_key) = db_with_key().await;
+        ApiKeyBMC::revoke(db.clone(), &new_key.api_key.id)
+            .await
+            .unwrap();
+
+        let api_key = ApiKeyBMC::authenticate(db, &new_key.key).await.unwrap();
+
+        assert!(api_key.is_none());
+    }
+}
@@ -0,0 +1,150 @@
+use actix_web::web::Data;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::time::Duration;
+use surrealdb::sql::{Object, Thing, Value};
+
+use crate::prelude::*;
+use crate::repository::surrealdb_repo::SurrealDBRepo;
+use crate::utils::macros::map;
+use crate::utils::x_take::XTakeVal;
+
+/// How long a client has to sign a challenge
+pub const CHALLENGE_TTL: Duration = Duration::from_secs(5 * 60);
+
+/// Nonce a client signs with the private key of its `publicKey` to sign in
+#[derive(Debug, Serialize)]
+pub struct Challenge {
+    pub nonce: String,
+    pub expires_in: u64,
+}
+
+/// Challenge consumed by [`AuthBMC::take_challenge`]
+#[derive(Debug)]
+pub struct PendingChallenge {
+    pub user_id: String,
+    pub public_key: String,
+}
+
+impl TryFrom<Object> for PendingChallenge {
+    type Error = Error;
+    fn try_from(mut val: Object) -> Result<PendingChallenge, Error> {
+        Ok(PendingChallenge {
+            user_id: val.x_take_val("userId")?,
+            public_key: val.x_take_val("publicKey")?,
+        })
+    }
+}
+
+/// Tokens handed to a user once signed in, the access token is a JWT and
+/// the refresh token can be exchanged once for a new pair
+#[derive(Debug, Serialize)]
+pub struct Tokens {
+    pub access_token: String,
+    pub token_type: &'static str,
+    pub expires_in: u64,
+    pub refresh_token: String,
+    pub refresh_expires_in: u64,
+}
+
+pub struct AuthBMC;
+
+impl AuthBMC {
+    /// Stores a fresh single use nonce for the user owning `public_key`
+    pub async fn create_challenge(
+        db: Data<SurrealDBRepo>,
+        user_id: &str,
+        public_key: &str,
+    ) -> Result<Challenge, Error> {
+        let sql = "CREATE $th SET userId = $userId, publicKey = $publicKey, \
+                   expiresAt = time::now() + $ttl";
+
+        let nonce = hex::encode(rand::random::<[u8; 32]>());
+
+        let vars: BTreeMap<String, Value> = map![
+            "th".into() => Thing::from(("challenge", nonce.as_str())).into(),
+            "userId".into() => user_id.into(),
+            "publicKey".into() => public_key.into(),
+            "ttl".into() => surrealdb::sql::Duration::from(CHALLENGE_TTL).into()];
+
+        db.execute(sql, Some(vars), true).await?;
+
+        Ok(Challenge {
+            nonce,
+            expires_in: CHALLENGE_TTL.as_secs(),
+        })
+    }
+
+    /// Deletes the challenge of `nonce` and returns it, `None` when it does
+    /// not exist or has expired
+    pub async fn take_challenge(
+        db: Data<SurrealDBRepo>,
+        nonce: &str,
+    ) -> Result<Option<PendingChallenge>, Error> {
+        db.execute(
+            "DELETE challenge WHERE expiresAt <= time::now()",
+            None,
+            true,
+        )
+        .await?;
+
+        let sql = "DELETE $th RETURN BEFORE";
+
+        let vars = map!["th".into() => Thing::from(("challenge", nonce)).into()];
+
+        let result = db.execute(sql, Some(vars), true).await?;
+
+        match result.first() {
+            Value::None => Ok(None),
+            value => Ok(Some(Object::try_from(W(value))?.try_into()?)),
+        }
+    }
+
+    /// Opens a session for `user_id` and returns its refresh token, only a
+    /// hash of the token is stored
+    pub async fn create_session(
+        db: Data<SurrealDBRepo>,
+        user_id: &str,
+        ttl: Duration,
+    ) -> Result<String, Error> {
+        let sql = "CREATE $th SET userId = $userId, expiresAt = time::now() + $ttl";
+
+        let token = URL_SAFE_NO_PAD.encode(rand::random::<[u8; 32]>());
+
+        let vars = map![
+            "th".into() => Self::session_thing(&token).into(),
+            "userId".into() => user_id.into(),
+            "ttl".into() => surrealdb::sql::Duration::from(ttl).into()];
+
+        db.execute(sql, Some(vars), true).await?;
+
+        Ok(token)
+    }
+
+    /// Closes the session of the refresh `token` and returns its user id,
+    /// `None` when the session does not exist or has expired
+    pub async fn take_session(
+        db: Data<SurrealDBRepo>,
+        token: &str,
+    ) -> Result<Option<String>, Error> {
+        db.execute("DELETE session WHERE expiresAt <= time::now()", None, true)
+            .await?;
+
+        let sql = "DELETE $th RETURN BEFORE";
+
+        let vars = map!["th".into() => Self::session_thing(token).into()];
+
+        let result = db.execute(sql, Some(vars), true).await?;
+
+        match result.first() {
+            Value::None => Ok(None),
+            value => Ok(Some(Object::try_from(W(value))?.x_take_val("userId")?)),
+        }
+    }
+
+    fn session_thing(token: &str) -> Thing {
+        Thing::from(("session", hex::encode(Sha256::digest(token)).as_str()))
+    }
+}
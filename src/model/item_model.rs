@@ -1,11 +1,18 @@
-use actix_web::web::Data;
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use std::collections::BTreeMap;
-use surrealdb::sql::{thing, Array, Object, Value};
+use std::time::SystemTime;
+use surrealdb::sql::{Datetime, Object, Thing, Value};
 
+use crate::model::api_key_model::Scope;
+use crate::policy::Owned;
 use crate::prelude::*;
-use crate::repository::surrealdb_repo::{Creatable, Patchable, SurrealDBRepo};
+use crate::repository::field::{Field, FieldKind};
+use crate::repository::sort::Sort;
+use crate::repository::surrealdb_repo::{Creatable, Patchable, Repository, SearchOp};
 use crate::utils::macros::map;
+use crate::utils::x_take::XTakeVal;
+use crate::validation::{Validate, Validator};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Item {
@@ -13,17 +20,52 @@ pub struct Item {
     pub cid: String,
     pub name: String,
     pub owner_id: String,
+    /// Owned by the server, bodies may leave it out, see
+    /// [`crate::repository::version::Versioned`]
+    #[serde(default)]
     pub version: u64,
-    pub content: Array,
+    pub content: Vec<String>,
     pub image_url: String,
-    pub creation_date: String,
-    pub edition_date: String,
-    pub tag_ids: Array,
-    pub follower_ids: Array,
+    /// Set by the server when the record is created, ignored in bodies
+    #[serde(skip_deserializing)]
+    pub creation_date: DateTime<Utc>,
+    /// Set by the server on every write, ignored in bodies
+    #[serde(skip_deserializing)]
+    pub edition_date: DateTime<Utc>,
+    pub tag_ids: Vec<String>,
+    pub follower_ids: Vec<String>,
     pub is_visible: bool,
     pub is_archived: bool,
 }
 
+impl Item {
+    pub fn last_modified(&self) -> SystemTime {
+        self.edition_date.into()
+    }
+}
+
+#[cfg(test)]
+impl Item {
+    /// Valid, visible item named `name`, for tests
+    pub fn fixture(owner_id: &str, name: &str) -> Self {
+        Item {
+            id: None,
+            cid: "cid".into(),
+            name: name.into(),
+            owner_id: owner_id.into(),
+            version: 0,
+            content: Vec::new(),
+            image_url: String::new(),
+            creation_date: Default::default(),
+            edition_date: Default::default(),
+            tag_ids: Vec::new(),
+            follower_ids: Vec::new(),
+            is_visible: true,
+            is_archived: false,
+        }
+    }
+}
+
 impl From<Item> for Value {
     fn from(val: Item) -> Self {
         match val.id {
@@ -35,8 +77,6 @@ impl From<Item> for Value {
               "version".into() => val.version.into(),
               "content".into() => val.content.into(),
               "imageUrl".into() => val.image_url.into(),
-              "creationDate".into() => val.creation_date.into(),
-              "editionDate".into() => val.edition_date.into(),
               "tagIds".into() => val.tag_ids.into(),
               "followerIds".into() => val.follower_ids.into(),
               "isVisible".into() => val.is_visible.into(),
@@ -50,8 +90,6 @@ impl From<Item> for Value {
               "version".into() => val.version.into(),
               "content".into() => val.content.into(),
               "imageUrl".into() => val.image_url.into(),
-              "creationDate".into() => val.creation_date.into(),
-              "editionDate".into() => val.edition_date.into(),
               "tagIds".into() => val.tag_ids.into(),
               "followerIds".into() => val.follower_ids.into(),
               "isVisible".into() => val.is_visible.into(),
@@ -62,20 +100,45 @@ impl From<Item> for Value {
     }
 }
 
+impl TryFrom<Object> for Item {
+    type Error = Error;
+    fn try_from(mut val: Object) -> Result<Item, Error> {
+        Ok(Item {
+            id: Some(val.x_take_val::<Thing>("id")?.id.to_raw()),
+            cid: val.x_take_val("cid")?,
+            name: val.x_take_val("name")?,
+            owner_id: val.x_take_val("ownerId")?,
+            version: val.x_take_val("version")?,
+            content: val.x_take_val("content")?,
+            image_url: val.x_take_val("imageUrl")?,
+            creation_date: val.x_take_val::<Datetime>("creationDate")?.0,
+            edition_date: val.x_take_val::<Datetime>("editionDate")?.0,
+            tag_ids: val.x_take_val("tagIds")?,
+            follower_ids: val.x_take_val("followerIds")?,
+            is_visible: val.x_take_val("isVisible")?,
+            is_archived: val.x_take_val("isArchived")?,
+        })
+    }
+}
+
+impl Validate for Item {
+    fn validate(&self, v: &mut Validator) {
+        v.name("name", Some(&self.name));
+        v.url("image_url", Some(&self.image_url));
+    }
+}
+
 impl Creatable for Item {}
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Default, Serialize, Deserialize)]
 pub struct ItemPatch {
     pub cid: Option<String>,
     pub name: Option<String>,
     pub owner_id: Option<String>,
-    pub version: Option<u64>,
-    pub content: Option<Array>,
+    pub content: Option<Vec<String>>,
     pub image_url: Option<String>,
-    pub creation_date: Option<String>,
-    pub edition_date: Option<String>,
-    pub tag_ids: Option<Array>,
-    pub follower_ids: Option<Array>,
+    pub tag_ids: Option<Vec<String>>,
+    pub follower_ids: Option<Vec<String>>,
     pub is_visible: Option<bool>,
     pub is_archived: Option<bool>,
 }
@@ -96,10 +159,6 @@ impl From<ItemPatch> for Value {
             value.insert("ownerId".into(), v.into());
         }
 
-        if let Some(v) = val.version {
-            value.insert("version".into(), v.into());
-        }
-
         if let Some(v) = val.content {
             value.insert("content".into(), v.into());
         }
@@ -108,14 +167,6 @@ impl From<ItemPatch> for Value {
             value.insert("imageUrl".into(), v.into());
         }
 
-        if let Some(v) = val.creation_date {
-            value.insert("creationDate".into(), v.into());
-        }
-
-        if let Some(v) = val.edition_date {
-            value.insert("editionDate".into(), v.into());
-        }
-
         if let Some(v) = val.tag_ids {
             value.insert("tagIds".into(), v.into());
         }
@@ -135,256 +186,87 @@ impl From<ItemPatch> for Value {
     }
 }
 
-impl Patchable for ItemPatch {}
-
-pub struct ItemBMC;
-
-impl ItemBMC {
-    pub async fn get_all(db: Data<SurrealDBRepo>) -> Result<Vec<Object>, Error> {
-        let ast = "SELECT * FROM item;";
-
-        let res = db.ds.execute(ast, &db.ses, None, true).await?;
-
-        let first_res = res.into_iter().next().expect("Did not get a response");
-
-        let array: Array = W(first_res.result?).try_into()?;
-
-        array.into_iter().map(|value| W(value).try_into()).collect()
-    }
-
-    pub async fn search_by_ids(
-        db: Data<SurrealDBRepo>,
-        ids: Vec<&str>,
-    ) -> Result<Vec<Object>, Error> {
-        let ast = "SELECT * FROM item WHERE id INSIDE $ids;";
-
-        let ids: Vec<String> = ids.iter().map(|id| format!("item:{}", id)).collect();
-        let ids_slice: Vec<&str> = ids.iter().map(|id| id.as_str()).collect();
-
-        let vars: BTreeMap<String, Value> = map!["ids".into() => ids_slice.into()];
-
-        let res = db.ds.execute(ast, &db.ses, Some(vars), true).await?;
-
-        let first_res = res.into_iter().next().expect("Did not get a response");
-
-        let array: Array = W(first_res.result?).try_into()?;
-
-        array.into_iter().map(|value| W(value).try_into()).collect()
-    }
-
-    pub async fn search_by_name(db: Data<SurrealDBRepo>, name: &str) -> Result<Vec<Object>, Error> {
-        let ast = "SELECT * FROM item WHERE name CONTAINS $name;";
-
-        let vars: BTreeMap<String, Value> = map!["name".into() => name.into()];
-
-        let res = db.ds.execute(ast, &db.ses, Some(vars), true).await?;
-
-        let first_res = res.into_iter().next().expect("Did not get a response");
-
-        let array: Array = W(first_res.result?).try_into()?;
-
-        array.into_iter().map(|value| W(value).try_into()).collect()
-    }
-
-    pub async fn search_by_owner_id(
-        db: Data<SurrealDBRepo>,
-        owner_id: &str,
-    ) -> Result<Vec<Object>, Error> {
-        let ast = "SELECT * FROM item WHERE ownerId = $owner_id;";
-
-        let vars: BTreeMap<String, Value> = map!["owner_id".into() => owner_id.into()];
-
-        let res = db.ds.execute(ast, &db.ses, Some(vars), true).await?;
-
-        let first_res = res.into_iter().next().expect("Did not get a response");
-
-        let array: Array = W(first_res.result?).try_into()?;
-
-        array.into_iter().map(|value| W(value).try_into()).collect()
-    }
-
-    pub async fn search_by_creation_date(
-        db: Data<SurrealDBRepo>,
-        creation_date: &str,
-    ) -> Result<Vec<Object>, Error> {
-        let ast = "SELECT * FROM item WHERE creationDate = $creation_date;";
-
-        let vars: BTreeMap<String, Value> = map!["creation_date".into() => creation_date.into()];
-
-        let res = db.ds.execute(ast, &db.ses, Some(vars), true).await?;
-
-        let first_res = res.into_iter().next().expect("Did not get a response");
-
-        let array: Array = W(first_res.result?).try_into()?;
-
-        array.into_iter().map(|value| W(value).try_into()).collect()
-    }
-
-    pub async fn search_by_edition_date(
-        db: Data<SurrealDBRepo>,
-        edition_date: &str,
-    ) -> Result<Vec<Object>, Error> {
-        let ast = "SELECT * FROM item WHERE editionDate = $edition_date;";
-
-        let vars: BTreeMap<String, Value> = map!["edition_date".into() => edition_date.into()];
-
-        let res = db.ds.execute(ast, &db.ses, Some(vars), true).await?;
-
-        let first_res = res.into_iter().next().expect("Did not get a response");
-
-        let array: Array = W(first_res.result?).try_into()?;
-
-        array.into_iter().map(|value| W(value).try_into()).collect()
-    }
-
-    pub async fn search_by_tag_ids(
-        db: Data<SurrealDBRepo>,
-        tag_ids: Vec<&str>,
-    ) -> Result<Vec<Object>, Error> {
-        let ast = "SELECT * FROM item WHERE tagIds INSIDE $ids;";
-
-        let ids: Vec<String> = tag_ids.iter().map(|id| format!("item:{}", id)).collect();
-        let ids_slice: Vec<&str> = ids.iter().map(|id| id.as_str()).collect();
-
-        let vars: BTreeMap<String, Value> = map!["ids".into() => ids_slice.into()];
-
-        let res = db.ds.execute(ast, &db.ses, Some(vars), true).await?;
-
-        let first_res = res.into_iter().next().expect("Did not get a response");
-
-        let array: Array = W(first_res.result?).try_into()?;
-
-        array.into_iter().map(|value| W(value).try_into()).collect()
-    }
-
-    pub async fn search_by_follower_ids(
-        db: Data<SurrealDBRepo>,
-        follower_ids: Vec<&str>,
-    ) -> Result<Vec<Object>, Error> {
-        let ast = "SELECT * FROM item WHERE followerIds INSIDE $ids;";
-
-        let ids: Vec<String> = follower_ids
-            .iter()
-            .map(|id| format!("item:{}", id))
-            .collect();
-        let ids_slice: Vec<&str> = ids.iter().map(|id| id.as_str()).collect();
-
-        let vars: BTreeMap<String, Value> = map!["ids".into() => ids_slice.into()];
-
-        let res = db.ds.execute(ast, &db.ses, Some(vars), true).await?;
-
-        let first_res = res.into_iter().next().expect("Did not get a response");
-
-        let array: Array = W(first_res.result?).try_into()?;
-
-        array.into_iter().map(|value| W(value).try_into()).collect()
-    }
-
-    pub async fn search_by_is_visible(
-        db: Data<SurrealDBRepo>,
-        is_visible: bool,
-    ) -> Result<Vec<Object>, Error> {
-        let ast = "SELECT * FROM item WHERE isVisible = $is_visible;";
-
-        let vars: BTreeMap<String, Value> = map!["is_visible".into() => is_visible.into()];
-
-        let res = db.ds.execute(ast, &db.ses, Some(vars), true).await?;
-
-        let first_res = res.into_iter().next().expect("Did not get a response");
-
-        let array: Array = W(first_res.result?).try_into()?;
-
-        array.into_iter().map(|value| W(value).try_into()).collect()
+impl Validate for ItemPatch {
+    fn validate(&self, v: &mut Validator) {
+        v.name("name", self.name.as_deref());
+        v.url("image_url", self.image_url.as_deref());
     }
+}
 
-    pub async fn search_by_is_archived(
-        db: Data<SurrealDBRepo>,
-        is_archived: bool,
-    ) -> Result<Vec<Object>, Error> {
-        let ast = "SELECT * FROM item WHERE isArchived = $is_archived;";
-
-        let vars: BTreeMap<String, Value> = map!["is_archived".into() => is_archived.into()];
+impl Patchable for ItemPatch {}
 
-        let res = db.ds.execute(ast, &db.ses, Some(vars), true).await?;
+/// Fields of [`Item`] clients are allowed to search on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ItemField {
+    Name,
+    OwnerId,
+    CreationDate,
+    EditionDate,
+    TagIds,
+    FollowerIds,
+    IsVisible,
+    IsArchived,
+}
 
-        let first_res = res.into_iter().next().expect("Did not get a response");
-        let array: Array = W(first_res.result?).try_into()?;
-        array.into_iter().map(|value| W(value).try_into()).collect()
+impl Field for ItemField {
+    const ALL: &'static [Self] = &[
+        ItemField::Name,
+        ItemField::OwnerId,
+        ItemField::CreationDate,
+        ItemField::EditionDate,
+        ItemField::TagIds,
+        ItemField::FollowerIds,
+        ItemField::IsVisible,
+        ItemField::IsArchived,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            ItemField::Name => "name",
+            ItemField::OwnerId => "ownerId",
+            ItemField::CreationDate => "creationDate",
+            ItemField::EditionDate => "editionDate",
+            ItemField::TagIds => "tagIds",
+            ItemField::FollowerIds => "followerIds",
+            ItemField::IsVisible => "isVisible",
+            ItemField::IsArchived => "isArchived",
+        }
     }
 
-    pub async fn create<T: Creatable>(
-        db: Data<SurrealDBRepo>,
-        tb: &str,
-        data: T,
-    ) -> Result<Object, Error> {
-        let sql = "CREATE type::table($tb) CONTENT $data RETURN *";
-
-        let data: Object = W(data.into()).try_into()?;
-
-        let vars: BTreeMap<String, Value> = map![
-    "tb".into() => tb.into(),
-    "data".into() => Value::from(data)];
-
-        let ress = db.ds.execute(sql, &db.ses, Some(vars), false).await?;
-
-        let first_val = ress
-            .into_iter()
-            .next()
-            .map(|r| r.result)
-            .expect("id not returned")?;
-
-        W(first_val.first()).try_into()
+    fn kind(self) -> FieldKind {
+        match self {
+            ItemField::Name | ItemField::OwnerId => FieldKind::Text,
+            ItemField::CreationDate | ItemField::EditionDate => FieldKind::Datetime,
+            ItemField::TagIds | ItemField::FollowerIds => FieldKind::TextList,
+            ItemField::IsVisible | ItemField::IsArchived => FieldKind::Bool,
+        }
     }
 
-    pub async fn get(db: Data<SurrealDBRepo>, tid: &str) -> Result<Object, Error> {
-        let sql = "SELECT * FROM $th";
-
-        let tid = format!("item:{}", tid);
-
-        let vars: BTreeMap<String, Value> = map!["th".into() => thing(&tid)?.into()];
-
-        let ress = db.ds.execute(sql, &db.ses, Some(vars), true).await?;
-
-        let first_res = ress.into_iter().next().expect("Did not get a response");
-
-        W(first_res.result?.first()).try_into()
+    fn search_op(self) -> SearchOp {
+        match self {
+            ItemField::Name => SearchOp::Contains,
+            ItemField::TagIds | ItemField::FollowerIds => SearchOp::ContainsAny,
+            _ => SearchOp::Eq,
+        }
     }
+}
 
-    pub async fn update<T: Patchable>(
-        db: Data<SurrealDBRepo>,
-        tid: &str,
-        data: T,
-    ) -> Result<Object, Error> {
-        let sql = "UPDATE $th MERGE $data RETURN *";
-
-        let tid = format!("item:{}", tid);
-
-        let vars = map![
-    "th".into() => thing(&tid)?.into(),
-    "data".into() => data.into()];
-
-        let ress = db.ds.execute(sql, &db.ses, Some(vars), true).await?;
-
-        let first_res = ress.into_iter().next().expect("id not returned");
+pub struct ItemBMC;
 
-        let result = first_res.result?;
+impl Repository for ItemBMC {
+    const TABLE: &'static str = "item";
+    type Model = Item;
+    type Field = ItemField;
 
-        W(result.first()).try_into()
+    fn default_sort() -> Vec<Sort<ItemField>> {
+        vec![Sort::asc(ItemField::CreationDate)]
     }
+}
 
-    pub async fn delete(db: Data<SurrealDBRepo>, tid: &str) -> Result<String, Error> {
-        let sql = "DELETE $th RETURN *";
-
-        let tid = format!("item:{}", tid);
-
-        let vars = map!["th".into() => thing(&tid)?.into()];
-
-        let ress = db.ds.execute(sql, &db.ses, Some(vars), false).await?;
-
-        let first_res = ress.into_iter().next().expect("id not returned");
-
-        first_res.result?;
+impl Owned for ItemBMC {
+    const WRITE_SCOPE: Scope = Scope::WriteItems;
 
-        Ok(tid)
+    fn owner_id(item: &Item) -> &str {
+        &item.owner_id
     }
 }
@@ -1,3 +1,5 @@
 // model/mod.rs
+pub mod api_key_model;
+pub mod auth_model;
 pub mod item_model;
 pub mod user_model;
@@ -1,27 +1,101 @@
 use actix_web::web::Data;
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use std::collections::BTreeMap;
-use surrealdb::sql::{thing, Array, Object, Value};
+use std::str::FromStr;
+use surrealdb::sql::{Datetime, Object, Thing, Value};
 
+use crate::model::api_key_model::Scope;
+use crate::policy::Owned;
 use crate::prelude::*;
-use crate::repository::surrealdb_repo::{Creatable, Patchable, SurrealDBRepo};
+use crate::repository::field::{Field, FieldKind};
+use crate::repository::sort::Sort;
+use crate::repository::surrealdb_repo::{
+    Creatable, Patchable, Repository, SearchOp, SurrealDBRepo,
+};
 use crate::utils::macros::map;
+use crate::utils::x_take::XTakeVal;
+use crate::validation::{Validate, Validator, ONLINE_STATES};
+
+/// Role of a user, each role is granted everything the previous ones are
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    #[default]
+    User,
+    Moderator,
+    Admin,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::User => "user",
+            Role::Moderator => "moderator",
+            Role::Admin => "admin",
+        }
+    }
+}
+
+impl FromStr for Role {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Role, Error> {
+        match s {
+            "user" => Ok(Role::User),
+            "moderator" => Ok(Role::Moderator),
+            "admin" => Ok(Role::Admin),
+            _ => Err(Error::InvalidFieldValue(
+                "role",
+                "one of user, moderator, admin",
+            )),
+        }
+    }
+}
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct User {
     pub id: Option<String>,
     pub cid: String,
     pub public_key: String,
-    pub private_key: String,
     pub name: String,
+    /// Owned by the server, bodies may leave it out, see
+    /// [`crate::repository::version::Versioned`]
+    #[serde(default)]
     pub version: u64,
     pub avatar: String,
     pub email: String,
-    pub creation_date: String,
+    /// Set by the server when the record is created, ignored in bodies
+    #[serde(skip_deserializing)]
+    pub creation_date: DateTime<Utc>,
     pub online_state: String,
-    pub follow_ids: Array,
+    pub follow_ids: Vec<String>,
     pub is_visible: bool,
     pub is_inactive: bool,
+    /// Only assigned by admins, see [`UserBMC::set_role`]
+    #[serde(default)]
+    pub role: Role,
+}
+
+#[cfg(test)]
+impl User {
+    /// Valid, visible user named `name`, for tests
+    pub fn fixture(name: &str) -> Self {
+        User {
+            id: None,
+            cid: "cid".into(),
+            public_key: name.into(),
+            name: name.into(),
+            version: 0,
+            avatar: String::new(),
+            email: format!("{}@example.com", name),
+            creation_date: Default::default(),
+            online_state: "online".into(),
+            follow_ids: Vec::new(),
+            is_visible: true,
+            is_inactive: false,
+            role: Role::User,
+        }
+    }
 }
 
 impl From<User> for Value {
@@ -31,51 +105,76 @@ impl From<User> for Value {
               "id".into() => v.into(),
               "cid".into() => val.cid.into(),
               "publicKey".into() => val.public_key.into(),
-              "privateKey".into() => val.private_key.into(),
               "name".into() => val.name.into(),
               "version".into() => val.version.into(),
               "avatar".into() => val.avatar.into(),
               "email".into() => val.email.into(),
-              "creationDate".into() => val.creation_date.into(),
               "onlineState".into() => val.online_state.into(),
               "followIds".into() => val.follow_ids.into(),
               "isVisible".into() => val.is_visible.into(),
               "isInactive".into() => val.is_inactive.into(),
+              "role".into() => val.role.as_str().into(),
             ]
             .into(),
             None => map![
               "cid".into() => val.cid.into(),
               "publicKey".into() => val.public_key.into(),
-              "privateKey".into() => val.private_key.into(),
               "name".into() => val.name.into(),
               "version".into() => val.version.into(),
               "avatar".into() => val.avatar.into(),
               "email".into() => val.email.into(),
-              "creationDate".into() => val.creation_date.into(),
               "onlineState".into() => val.online_state.into(),
               "followIds".into() => val.follow_ids.into(),
               "isVisible".into() => val.is_visible.into(),
               "isInactive".into() => val.is_inactive.into(),
+              "role".into() => val.role.as_str().into(),
             ]
             .into(),
         }
     }
 }
 
+impl TryFrom<Object> for User {
+    type Error = Error;
+    fn try_from(mut val: Object) -> Result<User, Error> {
+        Ok(User {
+            id: Some(val.x_take_val::<Thing>("id")?.id.to_raw()),
+            cid: val.x_take_val("cid")?,
+            public_key: val.x_take_val("publicKey")?,
+            name: val.x_take_val("name")?,
+            version: val.x_take_val("version")?,
+            avatar: val.x_take_val("avatar")?,
+            email: val.x_take_val("email")?,
+            creation_date: val.x_take_val::<Datetime>("creationDate")?.0,
+            online_state: val.x_take_val("onlineState")?,
+            follow_ids: val.x_take_val("followIds")?,
+            is_visible: val.x_take_val("isVisible")?,
+            is_inactive: val.x_take_val("isInactive")?,
+            role: val.x_take_val::<String>("role")?.parse()?,
+        })
+    }
+}
+
+impl Validate for User {
+    fn validate(&self, v: &mut Validator) {
+        v.name("name", Some(&self.name));
+        v.url("avatar", Some(&self.avatar));
+        v.email("email", Some(&self.email));
+        v.one_of("online_state", Some(&self.online_state), ONLINE_STATES);
+    }
+}
+
 impl Creatable for User {}
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Default, Serialize, Deserialize)]
 pub struct UserPatch {
     pub cid: Option<String>,
     pub public_key: Option<String>,
-    pub private_key: Option<String>,
     pub name: Option<String>,
-    pub version: Option<u64>,
     pub avatar: Option<String>,
     pub email: Option<String>,
-    pub creation_date: Option<String>,
     pub online_state: Option<String>,
-    pub follow_ids: Option<Array>,
+    pub follow_ids: Option<Vec<String>>,
     pub is_visible: Option<bool>,
     pub is_inactive: Option<bool>,
 }
@@ -92,18 +191,10 @@ impl From<UserPatch> for Value {
             value.insert("publicKey".into(), v.into());
         }
 
-        if let Some(v) = val.private_key {
-            value.insert("privateKey".into(), v.into());
-        }
-
         if let Some(v) = val.name {
             value.insert("name".into(), v.into());
         }
 
-        if let Some(v) = val.version {
-            value.insert("version".into(), v.into());
-        }
-
         if let Some(v) = val.avatar {
             value.insert("avatar".into(), v.into());
         }
@@ -112,10 +203,6 @@ impl From<UserPatch> for Value {
             value.insert("email".into(), v.into());
         }
 
-        if let Some(v) = val.creation_date {
-            value.insert("creationDate".into(), v.into());
-        }
-
         if let Some(v) = val.online_state {
             value.insert("onlineState".into(), v.into());
         }
@@ -135,164 +222,99 @@ impl From<UserPatch> for Value {
     }
 }
 
-impl Patchable for UserPatch {}
-
-pub struct UserBMC;
-
-impl UserBMC {
-    pub async fn get_all(db: Data<SurrealDBRepo>) -> Result<Vec<Object>, Error> {
-        let ast = "SELECT * FROM user;";
-
-        let res = db.ds.execute(ast, &db.ses, None, true).await?;
-
-        let first_res = res.into_iter().next().expect("Did not get a response");
-
-        let array: Array = W(first_res.result?).try_into()?;
-
-        array.into_iter().map(|value| W(value).try_into()).collect()
-    }
-
-    pub async fn search_by_ids(
-        db: Data<SurrealDBRepo>,
-        ids: Vec<&str>,
-    ) -> Result<Vec<Object>, Error> {
-        let ast = "SELECT * FROM user WHERE id INSIDE $ids;";
-
-        let ids: Vec<String> = ids.iter().map(|id| format!("user:{}", id)).collect();
-        let ids_slice: Vec<&str> = ids.iter().map(|id| id.as_str()).collect();
-
-        let vars: BTreeMap<String, Value> = map!["ids".into() => ids_slice.into()];
-
-        let res = db.ds.execute(ast, &db.ses, Some(vars), true).await?;
-
-        let first_res = res.into_iter().next().expect("Did not get a response");
-
-        let array: Array = W(first_res.result?).try_into()?;
-
-        array.into_iter().map(|value| W(value).try_into()).collect()
+impl Validate for UserPatch {
+    fn validate(&self, v: &mut Validator) {
+        v.name("name", self.name.as_deref());
+        v.url("avatar", self.avatar.as_deref());
+        v.email("email", self.email.as_deref());
+        v.one_of("online_state", self.online_state.as_deref(), ONLINE_STATES);
     }
+}
 
-    pub async fn search_by_public_key(
-        db: Data<SurrealDBRepo>,
-        public_key: &str,
-    ) -> Result<Vec<Object>, Error> {
-        let ast = "SELECT * FROM user WHERE publicKey = $public_key;";
-
-        let vars: BTreeMap<String, Value> = map!["public_key".into() => public_key.into()];
-
-        let res = db.ds.execute(ast, &db.ses, Some(vars), true).await?;
-
-        let first_res = res.into_iter().next().expect("Did not get a response");
-
-        let array: Array = W(first_res.result?).try_into()?;
-
-        array.into_iter().map(|value| W(value).try_into()).collect()
-    }
-
-    pub async fn search_by_name(db: Data<SurrealDBRepo>, name: &str) -> Result<Vec<Object>, Error> {
-        let ast = "SELECT * FROM user WHERE name CONTAINS $name;";
-
-        let vars: BTreeMap<String, Value> = map!["name".into() => name.into()];
-
-        let res = db.ds.execute(ast, &db.ses, Some(vars), true).await?;
-
-        let first_res = res.into_iter().next().expect("Did not get a response");
+impl Patchable for UserPatch {}
 
-        let array: Array = W(first_res.result?).try_into()?;
+/// Fields of [`User`] clients are allowed to search on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UserField {
+    PublicKey,
+    Name,
+    CreationDate,
+    IsInactive,
+}
 
-        array.into_iter().map(|value| W(value).try_into()).collect()
+impl Field for UserField {
+    const ALL: &'static [Self] = &[
+        UserField::PublicKey,
+        UserField::Name,
+        UserField::CreationDate,
+        UserField::IsInactive,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            UserField::PublicKey => "publicKey",
+            UserField::Name => "name",
+            UserField::CreationDate => "creationDate",
+            UserField::IsInactive => "isInactive",
+        }
     }
 
-    pub async fn search_by_is_inactive(
-        db: Data<SurrealDBRepo>,
-        is_inactive: bool,
-    ) -> Result<Vec<Object>, Error> {
-        let ast = "SELECT * FROM user WHERE isInactive = $is_inactive;";
-
-        let vars: BTreeMap<String, Value> = map!["is_inactive".into() => is_inactive.into()];
-
-        let res = db.ds.execute(ast, &db.ses, Some(vars), true).await?;
-
-        let first_res = res.into_iter().next().expect("Did not get a response");
-
-        let array: Array = W(first_res.result?).try_into()?;
-
-        array.into_iter().map(|value| W(value).try_into()).collect()
+    fn kind(self) -> FieldKind {
+        match self {
+            UserField::PublicKey | UserField::Name => FieldKind::Text,
+            UserField::CreationDate => FieldKind::Datetime,
+            UserField::IsInactive => FieldKind::Bool,
+        }
     }
 
-    pub async fn create<T: Creatable>(
-        db: Data<SurrealDBRepo>,
-        tb: &str,
-        data: T,
-    ) -> Result<Object, Error> {
-        let sql = "CREATE type::table($tb) CONTENT $data RETURN *";
-
-        let data: Object = W(data.into()).try_into()?;
-
-        let vars: BTreeMap<String, Value> = map![
-    "tb".into() => tb.into(),
-    "data".into() => Value::from(data)];
-
-        let ress = db.ds.execute(sql, &db.ses, Some(vars), false).await?;
-
-        let first_val = ress
-            .into_iter()
-            .next()
-            .map(|r| r.result)
-            .expect("id not returned")?;
-
-        W(first_val.first()).try_into()
+    fn search_op(self) -> SearchOp {
+        match self {
+            UserField::Name => SearchOp::Contains,
+            _ => SearchOp::Eq,
+        }
     }
+}
 
-    pub async fn get(db: Data<SurrealDBRepo>, tid: &str) -> Result<Object, Error> {
-        let sql = "SELECT * FROM $th";
-
-        let tid = format!("user:{}", tid);
-
-        let vars: BTreeMap<String, Value> = map!["th".into() => thing(&tid)?.into()];
-
-        let ress = db.ds.execute(sql, &db.ses, Some(vars), true).await?;
+pub struct UserBMC;
 
-        let first_res = ress.into_iter().next().expect("Did not get a response");
+impl Repository for UserBMC {
+    const TABLE: &'static str = "user";
+    type Model = User;
+    type Field = UserField;
 
-        W(first_res.result?.first()).try_into()
+    fn default_sort() -> Vec<Sort<UserField>> {
+        vec![Sort::asc(UserField::CreationDate)]
     }
+}
 
-    pub async fn update<T: Patchable>(
-        db: Data<SurrealDBRepo>,
-        tid: &str,
-        data: T,
-    ) -> Result<Object, Error> {
-        let sql = "UPDATE $th MERGE $data RETURN *";
+impl UserBMC {
+    pub async fn set_role(db: Data<SurrealDBRepo>, tid: &str, role: Role) -> Result<User, Error> {
+        // Fail with a 404 instead of creating the user
+        Self::get(db.clone(), tid).await?;
 
-        let tid = format!("user:{}", tid);
+        let sql = "UPDATE $th SET role = $role, version += 1 RETURN *";
 
         let vars = map![
-    "th".into() => thing(&tid)?.into(),
-    "data".into() => data.into()];
-
-        let ress = db.ds.execute(sql, &db.ses, Some(vars), true).await?;
+            "th".into() => Self::thing(tid).into(),
+            "role".into() => role.as_str().into()];
 
-        let first_res = ress.into_iter().next().expect("id not returned");
+        let result = db.execute(sql, Some(vars), true).await?;
 
-        let result = first_res.result?;
-
-        W(result.first()).try_into()
+        Object::try_from(W(result.first()))?.try_into()
     }
+}
 
-    pub async fn delete(db: Data<SurrealDBRepo>, tid: &str) -> Result<String, Error> {
-        let sql = "DELETE $th RETURN *";
-
-        let tid = format!("user:{}", tid);
-
-        let vars = map!["th".into() => thing(&tid)?.into()];
-
-        let ress = db.ds.execute(sql, &db.ses, Some(vars), false).await?;
-
-        let first_res = ress.into_iter().next().expect("id not returned");
+/// Users own their own record
+impl Owned for UserBMC {
+    const WRITE_SCOPE: Scope = Scope::Admin;
 
-        first_res.result?;
+    fn owner_id(user: &User) -> &str {
+        user.id.as_deref().unwrap_or_default()
+    }
 
-        Ok(tid)
+    /// Users keep their id, and their role is only changed by admins
+    fn carry_over(current: &User, data: &mut User) {
+        data.id = current.id.clone();
+        data.role = current.role;
     }
 }
@@ -0,0 +1,306 @@
+use actix_web::web::Data;
+
+use crate::ctx::AuthUser;
+use crate::model::api_key_model::Scope;
+use crate::model::user_model::Role;
+use crate::prelude::*;
+use crate::repository::surrealdb_repo::{Creatable, Repository, SurrealDBRepo};
+use crate::repository::version::ExpectedVersion;
+
+/// Repository whose records belong to a user, only that user or an admin
+/// may change them
+///
+/// Handlers mutate owned records through [`Owned::replace_as`],
+/// [`Owned::patch_as`] and [`Owned::delete_as`] so the check cannot be
+/// forgotten per route.
+///
+/// ## Usage
+/// ```no_run
+/// impl Owned for ItemBMC {
+///     const WRITE_SCOPE: Scope = Scope::WriteItems;
+///
+///     fn owner_id(item: &Item) -> &str {
+///         &item.owner_id
+///     }
+/// }
+///
+/// ItemBMC::replace_as(db, &auth, "1", item, ExpectedVersion::Body(3)).await?;
+/// ```
+#[allow(async_fn_in_trait)]
+pub trait Owned: Repository {
+    /// Scope an API key needs to change the records
+    const WRITE_SCOPE: Scope;
+
+    /// Id of the user owning `record`
+    fn owner_id(record: &Self::Model) -> &str;
+
+    /// Copies the fields clients may not write from `current` to `data`
+    /// replacing it
+    fn carry_over(_current: &Self::Model, _data: &mut Self::Model) {}
+
+    /// Loads the record `tid` and checks that `auth` may change it, `404`
+    /// when it does not exist and `403` when it belongs to someone else or
+    /// the API key lacks [`Owned::WRITE_SCOPE`]
+    async fn authorize(
+        db: Data<SurrealDBRepo>,
+        auth: &AuthUser,
+        tid: &str,
+    ) -> Result<Self::Model, Error> {
+        auth.require_scope(Self::WRITE_SCOPE)?;
+
+        let record = Self::get(db, tid).await?;
+
+        if !may_act_for(auth, Self::owner_id(&record)) {
+            return Err(Error::Forbidden(format!(
+                "'{}' belongs to another user",
+                Self::thing_id(tid)
+            )));
+        }
+
+        Ok(record)
+    }
+
+    /// Replaces the record `tid` with `data`, see [`Owned::patch_as`]
+    async fn replace_as(
+        db: Data<SurrealDBRepo>,
+        auth: &AuthUser,
+        tid: &str,
+        data: Self::Model,
+        expected: ExpectedVersion,
+    ) -> Result<Self::Model, Error>
+    where
+        Self::Model: Creatable,
+    {
+        Self::patch_as(db, auth, tid, expected, |_| Ok(data)).await
+    }
+
+    /// Replaces the record `tid` with what `patch` makes of it, once
+    /// authorized, the new record has to belong to someone `auth` may act for
+    async fn patch_as<F>(
+        db: Data<SurrealDBRepo>,
+        auth: &AuthUser,
+        tid: &str,
+        expected: ExpectedVersion,
+        patch: F,
+    ) -> Result<Self::Model, Error>
+    where
+        Self::Model: Creatable,
+        F: FnOnce(&Self::Model) -> Result<Self::Model, Error>,
+    {
+        let current = Self::authorize(db.clone(), auth, tid).await?;
+
+        let mut data = patch(&current)?;
+        Self::carry_over(&current, &mut data);
+
+        if !may_act_for(auth, Self::owner_id(&data)) {
+            return Err(Error::Forbidden(format!(
+                "'{}' can only be given to yourself",
+                Self::thing_id(tid)
+            )));
+        }
+
+        Self::replace(db, tid, data, expected).await
+    }
+
+    async fn delete_as(
+        db: Data<SurrealDBRepo>,
+        auth: &AuthUser,
+        tid: &str,
+    ) -> Result<String, Error> {
+        Self::authorize(db.clone(), auth, tid).await?;
+
+        Self::delete(db, tid).await
+    }
+}
+
+/// Whether `auth` may act on behalf of the user `owner_id`, admins may act
+/// for anyone
+pub fn may_act_for(auth: &AuthUser, owner_id: &str) -> bool {
+    auth.user_id() == owner_id || auth.role() >= Role::Admin
+}
+
+#[cfg(all(test, feature = "kv-mem"))]
+mod tests {
+    use super::*;
+    use crate::model::item_model::{Item, ItemBMC};
+    use crate::model::user_model::{User, UserBMC};
+
+    /// Store holding an item of `owner`, and the id of the item
+    async fn db_with_item() -> (Data<SurrealDBRepo>, String) {
+        let db = Data::new(SurrealDBRepo::in_memory().await.unwrap());
+        let item = ItemBMC::create(db.clone(), Item::fixture("owner", "name"))
+            .await
+            .unwrap();
+        (db, item.id.unwrap())
+    }
+
+    fn user(user_id: &str) -> AuthUser {
+        AuthUser::fixture(user_id, Role::User, None)
+    }
+
+    /// Patch renaming the item, as a client would
+    fn renamed(item: &Item, name: &str) -> Result<Item, Error> {
+        Ok(Item::fixture(&item.owner_id, name))
+    }
+
+    #[actix_web::test]
+    async fn owners_change_their_records() {
+        let (db, id) = db_with_item().await;
+        let owner = user("owner");
+
+        let replaced = ItemBMC::replace_as(
+            db.clone(),
+            &owner,
+            &id,
+            Item::fixture("owner", "replaced"),
+            ExpectedVersion::Body(0),
+        )
+        .await
+        .unwrap();
+        let patched =
+            ItemBMC::patch_as(db.clone(), &owner, &id, ExpectedVersion::Body(1), |item| {
+                renamed(item, "patched")
+            })
+            .await
+            .unwrap();
+        ItemBMC::delete_as(db.clone(), &owner, &id).await.unwrap();
+
+        assert_eq!(replaced.name, "replaced");
+        assert_eq!((patched.name.as_str(), patched.version), ("patched", 2));
+        assert!(matches!(
+            ItemBMC::get(db, &id).await,
+            Err(Error::StoreRecordNotFound(_))
+        ));
+    }
+
+    #[actix_web::test]
+    async fn other_users_are_forbidden() {
+        let (db, id) = db_with_item().await;
+        let other = user("other");
+
+        let replaced = ItemBMC::replace_as(
+            db.clone(),
+            &other,
+            &id,
+            Item::fixture("owner", "replaced"),
+            ExpectedVersion::Body(0),
+        )
+        .await;
+        let patched =
+            ItemBMC::patch_as(db.clone(), &other, &id, ExpectedVersion::Body(0), |item| {
+                renamed(item, "patched")
+            })
+            .await;
+        let deleted = ItemBMC::delete_as(db.clone(), &other, &id).await;
+
+        assert!(matches!(replaced, Err(Error::Forbidden(_))));
+        assert!(matches!(patched, Err(Error::Forbidden(_))));
+        assert!(matches!(deleted, Err(Error::Forbidden(_))));
+        assert_eq!(ItemBMC::get(db, &id).await.unwrap().name, "name");
+    }
+
+    #[actix_web::test]
+    async fn admins_change_records_of_anyone() {
+        let (db, id) = db_with_item().await;
+        let admin = AuthUser::fixture("admin", Role::Admin, None);
+
+        let patched =
+            ItemBMC::patch_as(db.clone(), &admin, &id, ExpectedVersion::Body(0), |item| {
+                renamed(item, "patched")
+            })
+            .await
+            .unwrap();
+        let given = ItemBMC::replace_as(
+            db.clone(),
+            &admin,
+            &id,
+            Item::fixture("someone", "given"),
+            ExpectedVersion::Body(1),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(patched.name, "patched");
+        assert_eq!(given.owner_id, "someone");
+        assert!(ItemBMC::delete_as(db, &admin, &id).await.is_ok());
+    }
+
+    #[actix_web::test]
+    async fn records_cannot_be_given_to_someone_else() {
+        let (db, id) = db_with_item().await;
+        let owner = user("owner");
+
+        let replaced = ItemBMC::replace_as(
+            db.clone(),
+            &owner,
+            &id,
+            Item::fixture("other", "given"),
+            ExpectedVersion::Body(0),
+        )
+        .await;
+        let patched =
+            ItemBMC::patch_as(db.clone(), &owner, &id, ExpectedVersion::Body(0), |item| {
+                Ok(Item {
+                    owner_id: "other".into(),
+                    ..renamed(item, "given")?
+                })
+            })
+            .await;
+
+        assert!(matches!(replaced, Err(Error::Forbidden(_))));
+        assert!(matches!(patched, Err(Error::Forbidden(_))));
+        assert_eq!(ItemBMC::get(db, &id).await.unwrap().owner_id, "owner");
+    }
+
+    #[actix_web::test]
+    async fn api_keys_need_the_write_scope() {
+        let (db, id) = db_with_item().await;
+        let read_only = AuthUser::fixture("owner", Role::User, Some(vec![Scope::ReadItems]));
+        let writer = AuthUser::fixture("owner", Role::User, Some(vec![Scope::WriteItems]));
+
+        let denied = ItemBMC::delete_as(db.clone(), &read_only, &id).await;
+        let patched =
+            ItemBMC::patch_as(db.clone(), &writer, &id, ExpectedVersion::Body(0), |item| {
+                renamed(item, "patched")
+            })
+            .await;
+
+        assert!(matches!(denied, Err(Error::Forbidden(_))));
+        assert!(patched.is_ok());
+    }
+
+    #[actix_web::test]
+    async fn missing_records_are_not_found() {
+        let (db, _) = db_with_item().await;
+
+        let deleted = ItemBMC::delete_as(db, &user("owner"), "missing").await;
+
+        assert!(matches!(deleted, Err(Error::StoreRecordNotFound(_))));
+    }
+
+    #[actix_web::test]
+    async fn users_keep_their_role_when_replacing_themselves() {
+        let db = Data::new(SurrealDBRepo::in_memory().await.unwrap());
+        let id = UserBMC::create(db.clone(), User::fixture("self"))
+            .await
+            .unwrap()
+            .id
+            .unwrap();
+
+        let replaced = UserBMC::replace_as(
+            db,
+            &user(&id),
+            &id,
+            User {
+                role: Role::Admin,
+                ..User::fixture("self")
+            },
+            ExpectedVersion::Body(0),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(replaced.role, Role::User);
+        assert_eq!(replaced.id, Some(id));
+    }
+}
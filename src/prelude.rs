@@ -13,12 +13,13 @@ pub struct W<T>(pub T);
 #[macro_export]
 macro_rules! internalError {
     ($l:literal) => {{
-        return Ok(actix_web::web::Json($crate::api::response::Response::new_error(500, $l.to_string())));
+        return Ok(actix_web::HttpResponse::InternalServerError().json(
+            $crate::api::response::Response::new_error(500, $l.to_string()),
+        ));
     }};
     () => {{
-        return Ok(actix_web::web::Json($crate::api::response::Response::new_error(
-            500,
-            "Internal Server Error".to_string(),
-        )));
+        return Ok(actix_web::HttpResponse::InternalServerError().json(
+            $crate::api::response::Response::new_error(500, "Internal Server Error".to_string()),
+        ));
     }};
 }
@@ -0,0 +1,472 @@
+use actix_web::{
+    body::EitherBody,
+    dev::{forward_ready, Payload, Service, ServiceRequest, ServiceResponse, Transform},
+    http::{
+        header::{HeaderName, HeaderValue},
+        Method,
+    },
+    FromRequest,
+};
+use futures::future::LocalBoxFuture;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::future::{ready, Ready};
+use std::rc::Rc;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::ctx::{AuthUser, API_KEY_HEADER};
+use crate::prelude::*;
+
+/// Requests a client may burst, refilled evenly over `period`
+///
+/// Read from the environment as `<requests>/<seconds>`, e.g. `120/60`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quota {
+    pub requests: u32,
+    pub period: Duration,
+}
+
+impl Quota {
+    /// Tokens refilled per second
+    fn rate(&self) -> f64 {
+        self.requests as f64 / self.period.as_secs_f64()
+    }
+}
+
+impl FromStr for Quota {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Quota, String> {
+        let invalid = || "expected <requests>/<seconds> with both above 0, e.g. 120/60".to_string();
+
+        let (requests, seconds) = s.split_once('/').ok_or_else(invalid)?;
+        let requests: u32 = requests.trim().parse().map_err(|_| invalid())?;
+        let seconds: u64 = seconds.trim().parse().map_err(|_| invalid())?;
+
+        if requests == 0 || seconds == 0 {
+            return Err(invalid());
+        }
+
+        Ok(Quota {
+            requests,
+            period: Duration::from_secs(seconds),
+        })
+    }
+}
+
+/// Routes sharing a quota, matched on their method and route pattern
+#[derive(Debug, Clone)]
+struct Group {
+    name: &'static str,
+    quota: Quota,
+    routes: Vec<(Method, &'static str)>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Outcome of taking a token, in whole requests and seconds as sent in the headers
+struct Decision {
+    allowed: bool,
+    limit: u32,
+    remaining: u32,
+    reset: u64,
+    retry_after: u64,
+}
+
+#[derive(Debug)]
+struct Buckets {
+    buckets: HashMap<String, Bucket>,
+    pruned: Instant,
+}
+
+/// Token bucket rate limiting middleware, every client gets a bucket per
+/// route group
+///
+/// Clients are told apart by their valid API key, the user of their valid
+/// access token and by their IP address otherwise. State is kept in process and shared by
+/// every worker, so the limiter must be built once outside of the app factory.
+///
+/// ## Usage
+/// ```no_run
+/// let limiter = RateLimiter::new(Quota { requests: 120, period: Duration::from_secs(60) })
+///     .group("create", strict, &[(Method::POST, "/api/items")]);
+///
+/// HttpServer::new(move || App::new().wrap(limiter.clone()))
+/// ```
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    default: Quota,
+    groups: Vec<Group>,
+    state: Arc<Mutex<Buckets>>,
+}
+
+impl RateLimiter {
+    pub fn new(default: Quota) -> Self {
+        RateLimiter {
+            default,
+            groups: Vec::new(),
+            state: Arc::new(Mutex::new(Buckets {
+                buckets: HashMap::new(),
+                pruned: Instant::now(),
+            })),
+        }
+    }
+
+    /// Gives `routes` their own quota, `routes` are route patterns as
+    /// registered, e.g. `/api/items/{id}`
+    pub fn group(
+        mut self,
+        name: &'static str,
+        quota: Quota,
+        routes: &[(Method, &'static str)],
+    ) -> Self {
+        self.groups.push(Group {
+            name,
+            quota,
+            routes: routes.to_vec(),
+        });
+        self
+    }
+
+    /// Group of the route, its name and quota
+    fn quota_for(&self, method: &Method, pattern: Option<&str>) -> (&'static str, Quota) {
+        self.groups
+            .iter()
+            .find(|group| {
+                group
+                    .routes
+                    .iter()
+                    .any(|(m, p)| m == method && Some(*p) == pattern)
+            })
+            .map(|group| (group.name, group.quota))
+            .unwrap_or(("default", self.default))
+    }
+
+    /// Takes a token from the bucket of `client` for the group
+    fn take(&self, group: &str, quota: Quota, client: &str) -> Decision {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap_or_else(|err| err.into_inner());
+
+        // Full buckets carry no state, drop them once per longest period
+        let longest = self
+            .groups
+            .iter()
+            .map(|group| group.quota.period)
+            .fold(self.default.period, Duration::max);
+        if now.duration_since(state.pruned) >= longest {
+            state
+                .buckets
+                .retain(|_, bucket| now.duration_since(bucket.updated) < longest);
+            state.pruned = now;
+        }
+
+        let limit = quota.requests as f64;
+        let bucket = state
+            .buckets
+            .entry(format!("{}:{}", group, client))
+            .or_insert(Bucket {
+                tokens: limit,
+                updated: now,
+            });
+
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * quota.rate()).min(limit);
+        bucket.updated = now;
+
+        let allowed = bucket.tokens >= 1.0;
+        if allowed {
+            bucket.tokens -= 1.0;
+        }
+
+        Decision {
+            allowed,
+            limit: quota.requests,
+            remaining: bucket.tokens.floor() as u32,
+            reset: ((limit - bucket.tokens) / quota.rate()).ceil() as u64,
+            retry_after: ((1.0 - bucket.tokens) / quota.rate()).ceil().max(1.0) as u64,
+        }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RateLimiter
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = actix_web::Error;
+    type Transform = RateLimiterMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RateLimiterMiddleware {
+            service: Rc::new(service),
+            limiter: self.clone(),
+        }))
+    }
+}
+
+pub struct RateLimiterMiddleware<S> {
+    service: Rc<S>,
+    limiter: RateLimiter,
+}
+
+impl<S, B> Service<ServiceRequest> for RateLimiterMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+        let limiter = self.limiter.clone();
+
+        Box::pin(async move {
+            // Only authenticated callers get a bucket of their own, anything
+            // else a client can vary, like an unknown API key, falls back to
+            // the IP address. The result is kept for the handler
+            let client = match AuthUser::from_request(req.request(), &mut Payload::None).await {
+                Ok(auth) => match req.headers().get(API_KEY_HEADER) {
+                    Some(key) => format!("key:{}", hex::encode(Sha256::digest(key.as_bytes()))),
+                    None => format!("user:{}", auth.user_id()),
+                },
+                Err(_) => format!(
+                    "ip:{}",
+                    req.peer_addr()
+                        .map(|addr| addr.ip().to_string())
+                        .unwrap_or_default()
+                ),
+            };
+
+            let (group, quota) = limiter.quota_for(req.method(), req.match_pattern().as_deref());
+            let decision = limiter.take(group, quota, &client);
+
+            let mut res = match decision.allowed {
+                true => service.call(req).await?.map_into_left_body(),
+                false => req
+                    .error_response(Error::RateLimited(decision.retry_after))
+                    .map_into_right_body(),
+            };
+
+            let headers = res.headers_mut();
+            for (name, value) in [
+                ("x-ratelimit-limit", decision.limit as u64),
+                ("x-ratelimit-remaining", decision.remaining as u64),
+                ("x-ratelimit-reset", decision.reset),
+            ] {
+                headers.insert(HeaderName::from_static(name), HeaderValue::from(value));
+            }
+
+            Ok(res)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quota(requests: u32, seconds: u64) -> Quota {
+        Quota {
+            requests,
+            period: Duration::from_secs(seconds),
+        }
+    }
+
+    #[test]
+    fn quota_parses_requests_per_seconds() {
+        assert_eq!("120/60".parse(), Ok(quota(120, 60)));
+        assert_eq!(" 5 / 1 ".parse(), Ok(quota(5, 1)));
+
+        for invalid in [
+            "", "120", "120/", "/60", "0/60", "120/0", "-1/60", "a/60", "1.5/60",
+        ] {
+            assert!(invalid.parse::<Quota>().is_err(), "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn take_spends_tokens_until_the_bucket_is_empty() {
+        let limiter = RateLimiter::new(quota(2, 60));
+
+        let first = limiter.take("default", quota(2, 60), "ip:a");
+        let second = limiter.take("default", quota(2, 60), "ip:a");
+        let third = limiter.take("default", quota(2, 60), "ip:a");
+
+        assert!(first.allowed);
+        assert_eq!((first.limit, first.remaining, first.reset), (2, 1, 30));
+        assert!(second.allowed);
+        assert_eq!((second.remaining, second.reset), (0, 60));
+        assert!(!third.allowed);
+        assert_eq!(
+            (third.remaining, third.reset, third.retry_after),
+            (0, 60, 30)
+        );
+    }
+
+    #[test]
+    fn take_keeps_a_bucket_per_client_and_group() {
+        let limiter = RateLimiter::new(quota(1, 60));
+
+        assert!(limiter.take("default", quota(1, 60), "ip:a").allowed);
+        assert!(!limiter.take("default", quota(1, 60), "ip:a").allowed);
+        assert!(limiter.take("default", quota(1, 60), "ip:b").allowed);
+        assert!(limiter.take("create", quota(1, 60), "ip:a").allowed);
+    }
+
+    #[test]
+    fn quota_for_picks_the_group_of_the_route() {
+        let limiter = RateLimiter::new(quota(120, 60)).group(
+            "create",
+            quota(20, 60),
+            &[(Method::POST, "/api/items")],
+        );
+
+        assert_eq!(
+            limiter.quota_for(&Method::POST, Some("/api/items")),
+            ("create", quota(20, 60))
+        );
+        assert_eq!(
+            limiter.quota_for(&Method::GET, Some("/api/items")),
+            ("default", quota(120, 60))
+        );
+        assert_eq!(
+            limiter.quota_for(&Method::POST, None),
+            ("default", quota(120, 60))
+        );
+    }
+
+    #[cfg(feature = "kv-mem")]
+    mod middleware {
+        use actix_web::{
+            dev::ServiceResponse,
+            http::{header, StatusCode},
+            test::{call_service, init_service, TestRequest},
+            web::{self, Data},
+            App, HttpResponse,
+        };
+
+        use super::*;
+        use crate::model::api_key_model::{ApiKeyBMC, Scope};
+        use crate::model::user_model::{User, UserBMC};
+        use crate::repository::surrealdb_repo::{Repository, SurrealDBRepo};
+
+        fn header<'a, B>(res: &'a ServiceResponse<B>, name: &str) -> &'a str {
+            res.headers().get(name).unwrap().to_str().unwrap()
+        }
+
+        fn post(key: Option<&str>) -> TestRequest {
+            let req = TestRequest::post()
+                .uri("/api/users")
+                .peer_addr("10.0.0.1:4000".parse().unwrap());
+            match key {
+                Some(key) => req.insert_header((API_KEY_HEADER, key)),
+                None => req,
+            }
+        }
+
+        async fn db() -> Data<SurrealDBRepo> {
+            Data::new(SurrealDBRepo::in_memory().await.unwrap())
+        }
+
+        macro_rules! app {
+            ($db:expr) => {
+                init_service(
+                    App::new()
+                        .app_data($db.clone())
+                        .route("/api/users", web::post().to(HttpResponse::Created))
+                        .wrap(RateLimiter::new(quota(120, 60)).group(
+                            "create",
+                            quota(2, 60),
+                            &[(Method::POST, "/api/users")],
+                        )),
+                )
+                .await
+            };
+        }
+
+        #[actix_web::test]
+        async fn responses_carry_the_state_of_the_bucket() {
+            let db = db().await;
+            let app = app!(db);
+
+            let first = call_service(&app, post(None).to_request()).await;
+            call_service(&app, post(None).to_request()).await;
+            let limited = call_service(&app, post(None).to_request()).await;
+
+            assert_eq!(first.status(), StatusCode::CREATED);
+            assert_eq!(header(&first, "x-ratelimit-limit"), "2");
+            assert_eq!(header(&first, "x-ratelimit-remaining"), "1");
+            assert_eq!(header(&first, "x-ratelimit-reset"), "30");
+            assert_eq!(limited.status(), StatusCode::TOO_MANY_REQUESTS);
+            assert_eq!(header(&limited, header::RETRY_AFTER.as_str()), "30");
+            assert_eq!(header(&limited, "x-ratelimit-remaining"), "0");
+            assert_eq!(header(&limited, "x-ratelimit-reset"), "60");
+        }
+
+        #[actix_web::test]
+        async fn unknown_api_keys_share_the_bucket_of_their_ip() {
+            let db = db().await;
+            let app = app!(db);
+
+            let statuses = [
+                call_service(&app, post(Some("one")).to_request())
+                    .await
+                    .status(),
+                call_service(&app, post(Some("two")).to_request())
+                    .await
+                    .status(),
+                call_service(&app, post(Some("three")).to_request())
+                    .await
+                    .status(),
+            ];
+
+            assert_eq!(
+                statuses,
+                [
+                    StatusCode::CREATED,
+                    StatusCode::CREATED,
+                    StatusCode::TOO_MANY_REQUESTS
+                ]
+            );
+        }
+
+        #[actix_web::test]
+        async fn valid_api_keys_get_their_own_bucket() {
+            let db = db().await;
+            let app = app!(db);
+            let user = UserBMC::create(db.clone(), User::fixture("owner"))
+                .await
+                .unwrap();
+            let key = ApiKeyBMC::create(
+                db.clone(),
+                user.id.as_deref().unwrap(),
+                "k",
+                &[Scope::ReadItems],
+            )
+            .await
+            .unwrap()
+            .key;
+
+            call_service(&app, post(None).to_request()).await;
+            call_service(&app, post(None).to_request()).await;
+            let anonymous = call_service(&app, post(None).to_request()).await;
+            let keyed = call_service(&app, post(Some(&key)).to_request()).await;
+
+            assert_eq!(anonymous.status(), StatusCode::TOO_MANY_REQUESTS);
+            assert_eq!(keyed.status(), StatusCode::CREATED);
+            assert_eq!(header(&keyed, "x-ratelimit-remaining"), "1");
+        }
+    }
+}
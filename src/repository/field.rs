@@ -0,0 +1,129 @@
+use chrono::{DateTime, Utc};
+use surrealdb::sql::{Datetime, Value};
+
+use crate::prelude::*;
+use crate::repository::surrealdb_repo::SearchOp;
+
+/// Type a whitelisted field is stored as, used to check client supplied values
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FieldKind {
+    Text,
+    Bool,
+    TextList,
+    /// RFC 3339 timestamp, stored as a datetime
+    Datetime,
+}
+
+impl FieldKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FieldKind::Text => "a string",
+            FieldKind::Bool => "a boolean",
+            FieldKind::TextList => "an array of strings",
+            FieldKind::Datetime => "an RFC 3339 timestamp, e.g. 2020-01-01T00:00:00Z",
+        }
+    }
+
+    /// Kind of a single element, lists hold text and scalars are their own element
+    pub fn element(&self) -> FieldKind {
+        match self {
+            FieldKind::TextList => FieldKind::Text,
+            kind => *kind,
+        }
+    }
+
+    /// Checks that `value` is of this kind and converts it for the store,
+    /// `field` is only used to name the field in the error
+    pub fn value(&self, field: &'static str, value: &serde_json::Value) -> Result<Value, Error> {
+        let invalid = || Error::InvalidFieldValue(field, self.as_str());
+
+        match (self, value) {
+            (FieldKind::Text, serde_json::Value::String(v)) => Ok(v.as_str().into()),
+            (FieldKind::Bool, serde_json::Value::Bool(v)) => Ok((*v).into()),
+            (FieldKind::Datetime, serde_json::Value::String(v)) => DateTime::parse_from_rfc3339(v)
+                .map(|v| Datetime::from(v.with_timezone(&Utc)).into())
+                .map_err(|_| invalid()),
+            (FieldKind::TextList, serde_json::Value::Array(arr)) => arr
+                .iter()
+                .map(|v| v.as_str().map(Value::from).ok_or_else(invalid))
+                .collect::<Result<Vec<Value>, Error>>()
+                .map(Value::from),
+            _ => Err(invalid()),
+        }
+    }
+
+    /// Reads a query string value as JSON of this kind, lists are comma separated
+    pub fn query_value(
+        &self,
+        field: &'static str,
+        value: &str,
+    ) -> Result<serde_json::Value, Error> {
+        match self {
+            FieldKind::Text | FieldKind::Datetime => Ok(value.into()),
+            FieldKind::Bool => value
+                .parse::<bool>()
+                .map(serde_json::Value::from)
+                .map_err(|_| Error::InvalidFieldValue(field, self.as_str())),
+            FieldKind::TextList => Ok(value
+                .split(',')
+                .map(str::trim)
+                .filter(|v| !v.is_empty())
+                .collect::<Vec<&str>>()
+                .into()),
+        }
+    }
+
+    /// Same as [`FieldKind::value`] for an array of values of this kind
+    pub fn values(&self, field: &'static str, value: &serde_json::Value) -> Result<Value, Error> {
+        match value {
+            serde_json::Value::Array(arr) => arr
+                .iter()
+                .map(|v| self.value(field, v))
+                .collect::<Result<Vec<Value>, Error>>()
+                .map(Value::from),
+            _ => Err(Error::InvalidFieldValue(field, "an array")),
+        }
+    }
+}
+
+/// Field of a model that clients are allowed to query on
+///
+/// ## Usage
+/// ```no_run
+/// let field = ItemField::parse("isVisible")?;
+/// let value = field.value(&serde_json::json!(true))?;
+/// ```
+pub trait Field: Copy + 'static {
+    /// Every queryable field, in the order they are reported to clients
+    const ALL: &'static [Self];
+
+    /// Name of the field as stored in the table
+    fn name(self) -> &'static str;
+
+    fn kind(self) -> FieldKind;
+
+    /// Comparison used when searching on this field alone
+    fn search_op(self) -> SearchOp {
+        match self.kind() {
+            FieldKind::TextList => SearchOp::ContainsAny,
+            FieldKind::Text | FieldKind::Bool | FieldKind::Datetime => SearchOp::Eq,
+        }
+    }
+
+    fn accepted() -> Vec<&'static str> {
+        Self::ALL.iter().map(|field| field.name()).collect()
+    }
+
+    fn parse(key: &str) -> Result<Self, Error> {
+        Self::ALL
+            .iter()
+            .find(|field| field.name() == key)
+            .copied()
+            .ok_or_else(|| Error::InvalidField(key.to_string(), Self::accepted()))
+    }
+
+    /// Checks that `value` matches the kind of the field and converts it for the store
+    fn value(self, value: &serde_json::Value) -> Result<Value, Error> {
+        self.kind().value(self.name(), value)
+    }
+}
@@ -0,0 +1,499 @@
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use surrealdb::sql::Value;
+
+use crate::prelude::*;
+use crate::repository::field::{Field, FieldKind};
+use crate::repository::surrealdb_repo::SearchOp;
+
+/// Filter expression sent by clients, compiled into a SurrealQL `WHERE` clause
+///
+/// Conditions reuse the `[field, value]` pair of the single field search and
+/// can be combined with `and` / `or`.
+///
+/// ## Usage
+/// Visible, non-archived items owned by X tagged Y:
+/// ```json
+/// {
+///     "and": [
+///         { "eq": ["isVisible", true] },
+///         { "eq": ["isArchived", false] },
+///         { "eq": ["ownerId", "X"] },
+///         { "contains": ["tagIds", "Y"] }
+///     ]
+/// }
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Filter {
+    And(Vec<Filter>),
+    Or(Vec<Filter>),
+    Eq(String, serde_json::Value),
+    Ne(String, serde_json::Value),
+    Contains(String, serde_json::Value),
+    In(String, serde_json::Value),
+    Gt(String, serde_json::Value),
+    Gte(String, serde_json::Value),
+    Lt(String, serde_json::Value),
+    Lte(String, serde_json::Value),
+}
+
+/// Query parameters used for sorting, pagination and date ranges, never read
+/// as fields
+pub const RESERVED_KEYS: &[&str] = &[
+    "sort",
+    "limit",
+    "start",
+    "cursor",
+    "total",
+    "createdAfter",
+    "createdBefore",
+    "editedSince",
+];
+
+/// Date range query parameters of the list endpoints, bounds are RFC 3339
+/// timestamps
+///
+/// ## Usage
+/// `GET /api/items?createdAfter=2020-01-01T00:00:00Z&editedSince=2020-06-01T00:00:00Z`
+/// ```no_run
+/// let filter = range_params.and::<ItemField>(filter)?;
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DateRange {
+    /// Created strictly after
+    pub created_after: Option<String>,
+    /// Created strictly before
+    pub created_before: Option<String>,
+    /// Edited at or after
+    pub edited_since: Option<String>,
+}
+
+/// Condition comparing a field to a bound
+type Bound = fn(String, serde_json::Value) -> Filter;
+
+impl DateRange {
+    /// Adds the bounds that are set to `filter`, bounds on a date the
+    /// records of `F` do not have are rejected
+    pub fn and<F: Field>(&self, filter: Option<Filter>) -> Result<Option<Filter>, Error> {
+        let bounds = [
+            (
+                &self.created_after,
+                "createdAfter",
+                "creationDate",
+                Filter::Gt as Bound,
+            ),
+            (
+                &self.created_before,
+                "createdBefore",
+                "creationDate",
+                Filter::Lt,
+            ),
+            (
+                &self.edited_since,
+                "editedSince",
+                "editionDate",
+                Filter::Gte,
+            ),
+        ];
+
+        let mut filters: Vec<Filter> = filter.into_iter().collect();
+
+        for (value, param, key, bound) in bounds {
+            let Some(value) = value else { continue };
+
+            let field = F::parse(key)
+                .map_err(|_| Error::InvalidInput(format!("'{}' is not supported here", param)))?;
+            let value = serde_json::Value::from(value.as_str());
+            field
+                .value(&value)
+                .map_err(|_| Error::InvalidFieldValue(param, FieldKind::Datetime.as_str()))?;
+
+            filters.push(bound(key.to_string(), value));
+        }
+
+        Ok(match filters.len() {
+            0 => None,
+            1 => filters.pop(),
+            _ => Some(Filter::And(filters)),
+        })
+    }
+}
+
+/// Compiled `WHERE` condition and the variables it binds
+#[derive(Debug, Default)]
+pub struct Clause {
+    pub sql: String,
+    pub vars: BTreeMap<String, Value>,
+}
+
+impl Clause {
+    /// Binds `value` to a fresh variable and returns its name
+    pub fn bind(&mut self, value: Value) -> String {
+        let name = format!("p{}", self.vars.len());
+        self.vars.insert(name.clone(), value);
+        format!("${}", name)
+    }
+}
+
+impl Filter {
+    /// Condition used when searching on `field` alone, see [`Field::search_op`]
+    pub fn search<F: Field>(field: F, value: serde_json::Value) -> Filter {
+        let key = field.name().to_string();
+
+        match field.search_op() {
+            SearchOp::Eq => Filter::Eq(key, value),
+            SearchOp::Contains => Filter::Contains(key, value),
+            SearchOp::ContainsAny | SearchOp::Inside => Filter::In(key, value),
+        }
+    }
+
+    /// Builds an `and` of single field searches from `field=value` query
+    /// pairs, skipping the [`RESERVED_KEYS`]
+    pub fn from_query<F: Field>(pairs: &[(String, String)]) -> Result<Filter, Error> {
+        let filters = pairs
+            .iter()
+            .filter(|(key, _)| !RESERVED_KEYS.contains(&key.as_str()))
+            .map(|(key, value)| {
+                let field = F::parse(key)?;
+                let value = field.kind().query_value(field.name(), value)?;
+                Ok(Filter::search(field, value))
+            })
+            .collect::<Result<Vec<Filter>, Error>>()?;
+
+        if filters.is_empty() {
+            return Err(Error::InvalidInput(format!(
+                "expected at least one of: {}",
+                F::accepted().join(", ")
+            )));
+        }
+
+        Ok(Filter::And(filters))
+    }
+
+    /// Compiles the expression for the fields `F`, every value is bound as a variable
+    pub fn compile<F: Field>(&self) -> Result<Clause, Error> {
+        let mut clause = Clause::default();
+        clause.sql = self.compile_into::<F>(&mut clause)?;
+        Ok(clause)
+    }
+
+    fn compile_into<F: Field>(&self, clause: &mut Clause) -> Result<String, Error> {
+        let (key, value, op) = match self {
+            Filter::And(filters) => return Self::join::<F>(filters, "AND", clause),
+            Filter::Or(filters) => return Self::join::<F>(filters, "OR", clause),
+            Filter::Eq(key, value) => (key, value, "eq"),
+            Filter::Ne(key, value) => (key, value, "ne"),
+            Filter::Contains(key, value) => (key, value, "contains"),
+            Filter::In(key, value) => (key, value, "in"),
+            Filter::Gt(key, value) => (key, value, "gt"),
+            Filter::Gte(key, value) => (key, value, "gte"),
+            Filter::Lt(key, value) => (key, value, "lt"),
+            Filter::Lte(key, value) => (key, value, "lte"),
+        };
+
+        let field = F::parse(key)?;
+        let name = field.name();
+        let kind = field.kind();
+
+        let (operator, value) = match (op, kind) {
+            ("eq", _) => ("=", kind.value(name, value)?),
+            ("ne", _) => ("!=", kind.value(name, value)?),
+            ("contains", FieldKind::Text | FieldKind::TextList) => {
+                ("CONTAINS", kind.element().value(name, value)?)
+            }
+            ("in", FieldKind::TextList) => ("CONTAINSANY", kind.element().values(name, value)?),
+            ("in", _) => ("INSIDE", kind.values(name, value)?),
+            ("gt", FieldKind::Text | FieldKind::Datetime) => (">", kind.value(name, value)?),
+            ("gte", FieldKind::Text | FieldKind::Datetime) => (">=", kind.value(name, value)?),
+            ("lt", FieldKind::Text | FieldKind::Datetime) => ("<", kind.value(name, value)?),
+            ("lte", FieldKind::Text | FieldKind::Datetime) => ("<=", kind.value(name, value)?),
+            _ => {
+                return Err(Error::InvalidFilter(format!(
+                    "operator '{}' cannot be used on '{}'",
+                    op, name
+                )))
+            }
+        };
+
+        Ok(format!("{} {} {}", name, operator, clause.bind(value)))
+    }
+
+    fn join<F: Field>(filters: &[Filter], sep: &str, clause: &mut Clause) -> Result<String, Error> {
+        if filters.is_empty() {
+            return Err(Error::InvalidFilter(format!(
+                "'{}' needs at least one condition",
+                sep.to_lowercase()
+            )));
+        }
+
+        let parts = filters
+            .iter()
+            .map(|filter| filter.compile_into::<F>(clause))
+            .collect::<Result<Vec<String>, Error>>()?;
+
+        Ok(format!("({})", parts.join(&format!(" {} ", sep))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, Utc};
+    use serde_json::json;
+    use surrealdb::sql::Datetime;
+
+    use super::*;
+    use crate::model::item_model::ItemField;
+    use crate::model::user_model::UserField;
+
+    const TS: &str = "2020-01-01T00:00:00Z";
+
+    fn compile(filter: serde_json::Value) -> Result<Clause, Error> {
+        serde_json::from_value::<Filter>(filter)
+            .unwrap()
+            .compile::<ItemField>()
+    }
+
+    fn datetime(ts: &str) -> Value {
+        Datetime::from(ts.parse::<DateTime<Utc>>().unwrap()).into()
+    }
+
+    #[test]
+    fn compile_nests_and_and_or() {
+        let clause = compile(json!({
+            "or": [
+                { "and": [
+                    { "eq": ["isVisible", true] },
+                    { "contains": ["tagIds", "t"] }
+                ] },
+                { "eq": ["ownerId", "o"] }
+            ]
+        }))
+        .unwrap();
+
+        assert_eq!(
+            clause.sql,
+            "((isVisible = $p0 AND tagIds CONTAINS $p1) OR ownerId = $p2)"
+        );
+        assert_eq!(clause.vars["p0"], Value::from(true));
+        assert_eq!(clause.vars["p1"], Value::from("t"));
+        assert_eq!(clause.vars["p2"], Value::from("o"));
+    }
+
+    /// Every operator on every kind of field, `None` when the pair is rejected
+    #[test]
+    fn compile_maps_each_operator_and_kind() {
+        let text = json!("x");
+        let boolean = json!(true);
+        let list = json!(["a", "b"]);
+        let date = json!(TS);
+
+        let cases = [
+            ("eq", "name", &text, Some("name = $p0")),
+            ("eq", "isVisible", &boolean, Some("isVisible = $p0")),
+            ("eq", "tagIds", &list, Some("tagIds = $p0")),
+            ("eq", "creationDate", &date, Some("creationDate = $p0")),
+            ("ne", "name", &text, Some("name != $p0")),
+            ("ne", "isVisible", &boolean, Some("isVisible != $p0")),
+            ("ne", "tagIds", &list, Some("tagIds != $p0")),
+            ("ne", "creationDate", &date, Some("creationDate != $p0")),
+            ("contains", "name", &text, Some("name CONTAINS $p0")),
+            ("contains", "tagIds", &text, Some("tagIds CONTAINS $p0")),
+            ("contains", "isVisible", &boolean, None),
+            ("contains", "creationDate", &date, None),
+            ("in", "name", &list, Some("name INSIDE $p0")),
+            ("in", "tagIds", &list, Some("tagIds CONTAINSANY $p0")),
+            (
+                "in",
+                "isVisible",
+                &json!([true]),
+                Some("isVisible INSIDE $p0"),
+            ),
+            (
+                "in",
+                "creationDate",
+                &json!([TS]),
+                Some("creationDate INSIDE $p0"),
+            ),
+            ("gt", "name", &text, Some("name > $p0")),
+            ("gt", "creationDate", &date, Some("creationDate > $p0")),
+            ("gt", "isVisible", &boolean, None),
+            ("gt", "tagIds", &list, None),
+            ("gte", "name", &text, Some("name >= $p0")),
+            ("gte", "creationDate", &date, Some("creationDate >= $p0")),
+            ("gte", "isVisible", &boolean, None),
+            ("gte", "tagIds", &list, None),
+            ("lt", "name", &text, Some("name < $p0")),
+            ("lt", "creationDate", &date, Some("creationDate < $p0")),
+            ("lt", "isVisible", &boolean, None),
+            ("lt", "tagIds", &list, None),
+            ("lte", "name", &text, Some("name <= $p0")),
+            ("lte", "creationDate", &date, Some("creationDate <= $p0")),
+            ("lte", "isVisible", &boolean, None),
+            ("lte", "tagIds", &list, None),
+        ];
+
+        for (op, field, value, expected) in cases {
+            let result = compile(json!({ op: [field, value] }));
+
+            match expected {
+                Some(sql) => {
+                    let clause = result.unwrap();
+                    assert_eq!(clause.sql, sql);
+                    assert_eq!(clause.vars.len(), 1, "{} on {}", op, field);
+                }
+                None => assert!(
+                    matches!(result, Err(Error::InvalidFilter(_))),
+                    "{} on {}",
+                    op,
+                    field
+                ),
+            }
+        }
+    }
+
+    #[test]
+    fn compile_binds_converted_values() {
+        let clause = compile(json!({
+            "and": [
+                { "gte": ["creationDate", TS] },
+                { "in": ["tagIds", ["a", "b"]] }
+            ]
+        }))
+        .unwrap();
+
+        assert_eq!(clause.vars["p0"], datetime(TS));
+        assert_eq!(
+            clause.vars["p1"],
+            Value::from(vec![Value::from("a"), Value::from("b")])
+        );
+    }
+
+    #[test]
+    fn compile_rejects_invalid_conditions() {
+        let cases = [
+            json!({ "and": [] }),
+            json!({ "or": [] }),
+            json!({ "and": [{ "eq": ["name", "x"] }, { "or": [] }] }),
+        ];
+
+        for filter in cases {
+            assert!(
+                matches!(compile(filter.clone()), Err(Error::InvalidFilter(_))),
+                "{}",
+                filter
+            );
+        }
+
+        assert!(matches!(
+            compile(json!({ "eq": ["secret", "x"] })),
+            Err(Error::InvalidField(key, _)) if key == "secret"
+        ));
+        assert!(matches!(
+            compile(json!({ "eq": ["name", 1] })),
+            Err(Error::InvalidFieldValue("name", _))
+        ));
+        assert!(matches!(
+            compile(json!({ "gt": ["creationDate", "yesterday"] })),
+            Err(Error::InvalidFieldValue("creationDate", _))
+        ));
+        assert!(matches!(
+            compile(json!({ "in": ["name", "x"] })),
+            Err(Error::InvalidFieldValue("name", _))
+        ));
+    }
+
+    #[test]
+    fn date_range_without_bounds_keeps_the_filter() {
+        let range = DateRange::default();
+
+        assert!(range.and::<ItemField>(None).unwrap().is_none());
+        assert!(matches!(
+            range.and::<ItemField>(Some(Filter::Eq("name".into(), "x".into()))),
+            Ok(Some(Filter::Eq(..)))
+        ));
+    }
+
+    #[test]
+    fn date_range_adds_each_bound() {
+        let cases = [
+            (
+                DateRange {
+                    created_after: Some(TS.into()),
+                    ..Default::default()
+                },
+                "creationDate > $p0",
+            ),
+            (
+                DateRange {
+                    created_before: Some(TS.into()),
+                    ..Default::default()
+                },
+                "creationDate < $p0",
+            ),
+            (
+                DateRange {
+                    edited_since: Some(TS.into()),
+                    ..Default::default()
+                },
+                "editionDate >= $p0",
+            ),
+        ];
+
+        for (range, sql) in cases {
+            let clause = range
+                .and::<ItemField>(None)
+                .unwrap()
+                .unwrap()
+                .compile::<ItemField>()
+                .unwrap();
+
+            assert_eq!(clause.sql, sql);
+            assert_eq!(clause.vars["p0"], datetime(TS));
+        }
+    }
+
+    #[test]
+    fn date_range_ands_the_bounds_with_the_filter() {
+        let range = DateRange {
+            created_after: Some(TS.into()),
+            created_before: Some("2021-01-01T00:00:00+01:00".into()),
+            edited_since: Some(TS.into()),
+        };
+
+        let clause = range
+            .and::<ItemField>(Some(Filter::Eq("name".into(), "x".into())))
+            .unwrap()
+            .unwrap()
+            .compile::<ItemField>()
+            .unwrap();
+
+        assert_eq!(
+            clause.sql,
+            "(name = $p0 AND creationDate > $p1 AND creationDate < $p2 AND editionDate >= $p3)"
+        );
+        assert_eq!(clause.vars["p2"], datetime("2020-12-31T23:00:00Z"));
+    }
+
+    #[test]
+    fn date_range_rejects_unknown_dates_and_invalid_timestamps() {
+        let edited = DateRange {
+            edited_since: Some(TS.into()),
+            ..Default::default()
+        };
+        let invalid = DateRange {
+            created_after: Some("2020-01-01".into()),
+            ..Default::default()
+        };
+
+        assert!(matches!(
+            edited.and::<UserField>(None),
+            Err(Error::InvalidInput(_))
+        ));
+        assert!(matches!(
+            invalid.and::<ItemField>(None),
+            Err(Error::InvalidFieldValue("createdAfter", _))
+        ));
+    }
+}
@@ -0,0 +1,125 @@
+use crate::prelude::*;
+use crate::repository::surrealdb_repo::SurrealDBRepo;
+use crate::utils::macros::map;
+use crate::utils::x_take::XTakeVal;
+
+/// Schema script applied once, in `version` order
+///
+/// Migrations are forward only: never edit one that has shipped, append a
+/// new one with the next version instead.
+pub struct Migration {
+    pub version: i64,
+    pub name: &'static str,
+    pub sql: &'static str,
+}
+
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "define_item_and_user",
+        sql: include_str!("../../migrations/0001_define_item_and_user.surql"),
+    },
+    Migration {
+        version: 2,
+        name: "define_indexes",
+        sql: include_str!("../../migrations/0002_define_indexes.surql"),
+    },
+    Migration {
+        version: 3,
+        name: "remove_private_key",
+        sql: include_str!("../../migrations/0003_remove_private_key.surql"),
+    },
+    Migration {
+        version: 4,
+        name: "define_auth",
+        sql: include_str!("../../migrations/0004_define_auth.surql"),
+    },
+    Migration {
+        version: 5,
+        name: "add_user_role",
+        sql: include_str!("../../migrations/0005_add_user_role.surql"),
+    },
+    Migration {
+        version: 6,
+        name: "define_api_key",
+        sql: include_str!("../../migrations/0006_define_api_key.surql"),
+    },
+    Migration {
+        version: 7,
+        name: "server_managed_dates",
+        sql: include_str!("../../migrations/0007_server_managed_dates.surql"),
+    },
+    Migration {
+        version: 8,
+        name: "define_date_indexes",
+        sql: include_str!("../../migrations/0008_define_date_indexes.surql"),
+    },
+];
+
+/// Table recording the applied migrations, one record per version
+const DEFINE_MIGRATIONS: &str = "
+    DEFINE TABLE _migrations SCHEMAFULL;
+    DEFINE FIELD version ON _migrations TYPE int ASSERT $value != NONE;
+    DEFINE FIELD name ON _migrations TYPE string ASSERT $value != NONE;
+    DEFINE FIELD appliedAt ON _migrations TYPE datetime ASSERT $value != NONE;
+";
+
+/// Applies the pending migrations, each in its own transaction, and returns
+/// the versions applied
+///
+/// ## Usage
+/// ```no_run
+/// let applied = migration::migrate(&db).await?;
+/// ```
+pub async fn migrate(db: &SurrealDBRepo) -> Result<Vec<i64>, Error> {
+    db.execute_script(DEFINE_MIGRATIONS, None).await?;
+
+    let applied = applied(db).await?;
+    let latest = MIGRATIONS.last().map_or(0, |m| m.version);
+
+    if let Some(version) = applied.iter().find(|version| **version > latest) {
+        return Err(Error::MigrationFailed(format!(
+            "datastore is at version {}, this build only knows up to {}",
+            version, latest
+        )));
+    }
+
+    let mut versions = Vec::new();
+
+    for migration in MIGRATIONS.iter().filter(|m| !applied.contains(&m.version)) {
+        let sql = format!(
+            "BEGIN TRANSACTION;
+            {}
+            CREATE type::thing('_migrations', $version)
+                SET version = $version, name = $name, appliedAt = time::now();
+            COMMIT TRANSACTION;",
+            migration.sql
+        );
+        let vars = map![
+            "version".into() => migration.version.into(),
+            "name".into() => migration.name.into(),
+        ];
+
+        db.execute_script(&sql, Some(vars)).await.map_err(|err| {
+            Error::MigrationFailed(format!("{} {}: {}", migration.version, migration.name, err))
+        })?;
+
+        log::info!("Applied migration {} {}", migration.version, migration.name);
+        versions.push(migration.version);
+    }
+
+    Ok(versions)
+}
+
+async fn applied(db: &SurrealDBRepo) -> Result<Vec<i64>, Error> {
+    let mut versions = db
+        .execute_objects("SELECT version FROM _migrations", None)
+        .await?
+        .into_iter()
+        .map(|mut obj| obj.x_take_val::<i64>("version"))
+        .collect::<Result<Vec<i64>, Error>>()?;
+
+    versions.sort_unstable();
+
+    Ok(versions)
+}
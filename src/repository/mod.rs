@@ -1,2 +1,8 @@
 // repository/mod.rs
+pub mod field;
+pub mod filter;
+pub mod migration;
+pub mod page;
+pub mod sort;
 pub mod surrealdb_repo;
+pub mod version;
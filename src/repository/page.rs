@@ -0,0 +1,317 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use serde::{Deserialize, Serialize};
+use surrealdb::sql::{Object, Thing, Value};
+
+use crate::prelude::*;
+use crate::repository::field::Field;
+use crate::repository::filter::Clause;
+use crate::repository::sort::Sort;
+
+pub const DEFAULT_LIMIT: u32 = 20;
+pub const MAX_LIMIT: u32 = 100;
+
+/// Pagination query parameters shared by the list endpoints
+///
+/// `start` skips a number of records, `cursor` continues from a `next` /
+/// `prev` cursor of a previous page and takes precedence over `start`.
+#[derive(Debug, Default, Deserialize)]
+pub struct PageParams {
+    pub limit: Option<u32>,
+    pub start: Option<u32>,
+    pub cursor: Option<String>,
+    #[serde(default)]
+    pub total: bool,
+}
+
+impl PageParams {
+    pub fn limit(&self) -> Result<u32, Error> {
+        match self.limit {
+            None => Ok(DEFAULT_LIMIT),
+            Some(limit @ 1..=MAX_LIMIT) => Ok(limit),
+            Some(_) => Err(Error::InvalidInput(format!(
+                "limit must be between 1 and {}",
+                MAX_LIMIT
+            ))),
+        }
+    }
+}
+
+/// One page of a list endpoint
+#[derive(Debug, Serialize)]
+pub struct Page<T> {
+    pub data: Vec<T>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prev: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Direction {
+    #[serde(rename = "n")]
+    Next,
+    #[serde(rename = "p")]
+    Prev,
+}
+
+/// Position of a record in a sorted listing, handed to clients base64 encoded
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Cursor {
+    #[serde(rename = "s")]
+    sort: String,
+    #[serde(rename = "v")]
+    values: Vec<serde_json::Value>,
+    id: String,
+    #[serde(rename = "d")]
+    pub dir: Direction,
+}
+
+impl Cursor {
+    /// Builds the cursor pointing at `obj` for the given ordering
+    pub fn at<F: Field>(obj: &Object, sort: &[Sort<F>], dir: Direction) -> Result<Self, Error> {
+        let values = sort
+            .iter()
+            .map(|s| {
+                let value = obj.get(s.field.name()).cloned().unwrap_or(Value::None);
+                Ok(serde_json::to_value(value)?)
+            })
+            .collect::<Result<Vec<serde_json::Value>, Error>>()?;
+
+        let id: Thing = W(obj.get("id").cloned().unwrap_or(Value::None)).try_into()?;
+
+        Ok(Cursor {
+            sort: Sort::to_list(sort),
+            values,
+            id: id.id.to_raw(),
+            dir,
+        })
+    }
+
+    pub fn encode(&self) -> Result<String, Error> {
+        Ok(URL_SAFE_NO_PAD.encode(serde_json::to_vec(self)?))
+    }
+
+    pub fn decode(cursor: &str) -> Result<Self, Error> {
+        URL_SAFE_NO_PAD
+            .decode(cursor)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .ok_or(Error::InvalidCursor)
+    }
+
+    /// Appends the keyset condition selecting records after (or before) the
+    /// cursor to `clause`, `thing` is the full record id of the cursor
+    pub fn condition<F: Field>(
+        &self,
+        sort: &[Sort<F>],
+        thing: Value,
+        clause: &mut Clause,
+    ) -> Result<String, Error> {
+        if self.sort != Sort::to_list(sort) || self.values.len() != sort.len() {
+            return Err(Error::InvalidCursor);
+        }
+
+        let op = |desc: bool| match (self.dir == Direction::Prev) ^ desc {
+            false => ">",
+            true => "<",
+        };
+
+        let mut equal: Vec<String> = Vec::new();
+        let mut terms: Vec<String> = Vec::new();
+
+        for (s, value) in sort.iter().zip(&self.values) {
+            let name = s.field.name();
+            let param = clause.bind(s.field.value(value).map_err(|_| Error::InvalidCursor)?);
+
+            terms.push(
+                equal
+                    .iter()
+                    .cloned()
+                    .chain([format!("{} {} {}", name, op(s.desc), param)])
+                    .collect::<Vec<String>>()
+                    .join(" AND "),
+            );
+            equal.push(format!("{} = {}", name, param));
+        }
+
+        let param = clause.bind(thing);
+        terms.push(
+            equal
+                .into_iter()
+                .chain([format!("id {} {}", op(false), param)])
+                .collect::<Vec<String>>()
+                .join(" AND "),
+        );
+
+        Ok(format!("(({}))", terms.join(") OR (")))
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+}
+
+#[cfg(all(test, feature = "kv-mem"))]
+mod tests {
+    use actix_web::web::Data;
+
+    use super::*;
+    use crate::model::item_model::{Item, ItemBMC, ItemField};
+    use crate::repository::filter::Filter;
+    use crate::repository::surrealdb_repo::{Repository, SurrealDBRepo};
+
+    async fn seed(names: &[&str]) -> Data<SurrealDBRepo> {
+        let db = Data::new(SurrealDBRepo::in_memory().await.unwrap());
+        for name in names {
+            ItemBMC::create(db.clone(), Item::fixture("owner", name))
+                .await
+                .unwrap();
+        }
+        db
+    }
+
+    async fn list(
+        db: &Data<SurrealDBRepo>,
+        sort: &str,
+        params: PageParams,
+    ) -> Result<Page<Item>, Error> {
+        let sort = Sort::<ItemField>::parse_list(sort)?;
+        ItemBMC::list(db.clone(), None, sort, &params).await
+    }
+
+    fn at(cursor: &Option<String>) -> PageParams {
+        PageParams {
+            limit: Some(2),
+            cursor: cursor.clone(),
+            ..Default::default()
+        }
+    }
+
+    fn names(page: &Page<Item>) -> Vec<&str> {
+        page.data.iter().map(|item| item.name.as_str()).collect()
+    }
+
+    fn ids(page: &Page<Item>) -> Vec<&str> {
+        page.data
+            .iter()
+            .filter_map(|item| item.id.as_deref())
+            .collect()
+    }
+
+    /// Follows `next` from the first page, returning every page
+    async fn walk(db: &Data<SurrealDBRepo>, sort: &str) -> Vec<Page<Item>> {
+        let mut pages = vec![list(db, sort, at(&None)).await.unwrap()];
+        while let Some(next) = &pages.last().unwrap().next {
+            pages.push(list(db, sort, at(&Some(next.clone()))).await.unwrap());
+        }
+        pages
+    }
+
+    #[actix_web::test]
+    async fn next_walks_every_record_once_in_order() {
+        let db = seed(&["c", "a", "b", "d", "b"]).await;
+
+        let pages = walk(&db, "name").await;
+
+        let all: Vec<&str> = pages.iter().flat_map(names).collect();
+        let mut seen: Vec<&str> = pages.iter().flat_map(ids).collect();
+        seen.sort();
+        seen.dedup();
+        assert_eq!(all, ["a", "b", "b", "c", "d"]);
+        assert_eq!(seen.len(), 5);
+        assert!(pages[0].prev.is_none());
+        assert!(pages.last().unwrap().next.is_none());
+    }
+
+    #[actix_web::test]
+    async fn descending_sorts_walk_backwards() {
+        let db = seed(&["c", "a", "b", "d", "b"]).await;
+
+        let pages = walk(&db, "-name").await;
+
+        let all: Vec<&str> = pages.iter().flat_map(names).collect();
+        assert_eq!(all, ["d", "c", "b", "b", "a"]);
+    }
+
+    #[actix_web::test]
+    async fn prev_returns_the_page_before() {
+        for sort in ["name", "-name"] {
+            let db = seed(&["c", "a", "b", "d", "b"]).await;
+            let pages = walk(&db, sort).await;
+            assert_eq!(pages.len(), 3);
+
+            let back = list(&db, sort, at(&pages[2].prev)).await.unwrap();
+            let first = list(&db, sort, at(&back.prev)).await.unwrap();
+
+            assert_eq!(ids(&back), ids(&pages[1]), "sort {}", sort);
+            assert_eq!(ids(&first), ids(&pages[0]), "sort {}", sort);
+            assert!(first.prev.is_none());
+            assert!(first.next.is_some());
+        }
+    }
+
+    #[actix_web::test]
+    async fn cursor_is_rejected_for_another_sort() {
+        let db = seed(&["a", "b", "c"]).await;
+        let first = list(&db, "name", at(&None)).await.unwrap();
+
+        let resorted = list(&db, "-name", at(&first.next)).await;
+        let garbage = list(&db, "name", at(&Some("garbage".into()))).await;
+
+        assert!(matches!(resorted, Err(Error::InvalidCursor)));
+        assert!(matches!(garbage, Err(Error::InvalidCursor)));
+    }
+
+    #[actix_web::test]
+    async fn start_skips_records_and_total_counts_every_match() {
+        let db = seed(&["c", "a", "b", "d", "b"]).await;
+
+        let page = list(
+            &db,
+            "name",
+            PageParams {
+                limit: Some(2),
+                start: Some(1),
+                total: true,
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+
+        let filter = Filter::Eq("name".into(), "b".into());
+        let params = PageParams {
+            limit: Some(1),
+            total: true,
+            ..Default::default()
+        };
+        let filtered = ItemBMC::list(db.clone(), Some(&filter), Vec::new(), &params)
+            .await
+            .unwrap();
+
+        assert_eq!(names(&page), ["b", "b"]);
+        assert_eq!(page.total, Some(5));
+        assert!(page.prev.is_some());
+        assert!(page.next.is_some());
+        assert_eq!(filtered.total, Some(2));
+        assert_eq!(filtered.data.len(), 1);
+    }
+
+    #[actix_web::test]
+    async fn limit_is_bounded() {
+        let db = seed(&[]).await;
+
+        for limit in [0, MAX_LIMIT + 1] {
+            let params = PageParams {
+                limit: Some(limit),
+                ..Default::default()
+            };
+            assert!(matches!(
+                list(&db, "name", params).await,
+                Err(Error::InvalidInput(_))
+            ));
+        }
+    }
+}
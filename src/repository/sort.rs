@@ -0,0 +1,87 @@
+use serde::Deserialize;
+
+use crate::prelude::*;
+use crate::repository::field::Field;
+
+/// Ordering on a single field, the record id is always appended as tie breaker
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sort<F: Field> {
+    pub field: F,
+    pub desc: bool,
+}
+
+impl<F: Field> Sort<F> {
+    pub fn asc(field: F) -> Self {
+        Sort { field, desc: false }
+    }
+
+    /// Parses a comma separated list of fields, a leading `-` sorts descending
+    ///
+    /// ## Usage
+    /// ```no_run
+    /// Sort::<ItemField>::parse_list("creationDate,-name")?;
+    /// ```
+    pub fn parse_list(list: &str) -> Result<Vec<Self>, Error> {
+        let mut sort: Vec<Self> = Vec::new();
+
+        for key in list.split(',').map(str::trim).filter(|key| !key.is_empty()) {
+            let (name, desc) = match key.strip_prefix('-') {
+                Some(name) => (name, true),
+                None => (key, false),
+            };
+
+            let field = F::parse(name)?;
+
+            if sort.iter().any(|s| s.field.name() == field.name()) {
+                return Err(Error::InvalidInput(format!(
+                    "'{}' is sorted on more than once",
+                    name
+                )));
+            }
+
+            sort.push(Sort { field, desc });
+        }
+
+        Ok(sort)
+    }
+
+    /// Inverse of [`Sort::parse_list`]
+    pub fn to_list(sort: &[Self]) -> String {
+        sort.iter()
+            .map(|s| match s.desc {
+                true => format!("-{}", s.field.name()),
+                false => s.field.name().to_string(),
+            })
+            .collect::<Vec<String>>()
+            .join(",")
+    }
+}
+
+/// `sort` query parameter shared by the list and search endpoints
+#[derive(Debug, Default, Deserialize)]
+pub struct SortParams {
+    pub sort: Option<String>,
+}
+
+impl SortParams {
+    pub fn parse<F: Field>(&self) -> Result<Vec<Sort<F>>, Error> {
+        match &self.sort {
+            Some(list) => Sort::parse_list(list),
+            None => Ok(Vec::new()),
+        }
+    }
+}
+
+/// `ORDER BY` clause for `sort`, every direction is flipped when `reverse` is set
+pub fn order_by<F: Field>(sort: &[Sort<F>], reverse: bool) -> String {
+    let dir = |desc: bool| match reverse ^ desc {
+        false => "ASC",
+        true => "DESC",
+    };
+
+    sort.iter()
+        .map(|s| format!("{} {}", s.field.name(), dir(s.desc)))
+        .chain([format!("id {}", dir(false))])
+        .collect::<Vec<String>>()
+        .join(", ")
+}
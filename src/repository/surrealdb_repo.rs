@@ -1,8 +1,22 @@
+use actix_web::web::Data;
+use std::collections::BTreeMap;
 use std::sync::Arc;
-use surrealdb::{sql::Value, Datastore, Error, Session};
+use surrealdb::sql::{Array, Object, Thing, Value};
+use surrealdb::{dbs::Session, error::Db, kvs::Datastore};
 
-pub trait Creatable: Into<Value> {}
-pub trait Patchable: Into<Value> {}
+use crate::config::Config;
+use crate::prelude::*;
+use crate::repository::field::Field;
+use crate::repository::filter::{Clause, Filter};
+use crate::repository::page::{Cursor, Direction, Page, PageParams};
+use crate::repository::sort::{order_by, Sort};
+use crate::repository::version::ExpectedVersion;
+use crate::utils::macros::map;
+use crate::utils::x_take::XTakeVal;
+use crate::validation::Validate;
+
+pub trait Creatable: Into<Value> + Validate {}
+pub trait Patchable: Into<Value> + Validate {}
 
 // https://github.dev/ixhbinphoenix/bne
 // https://github.com/ixhbinphoenix/actix-session-surrealdb
@@ -13,12 +27,519 @@ pub struct SurrealDBRepo {
     pub ses: Session,
 }
 
+/// Datastore engines by URL scheme, with the cargo feature compiling each in
+const ENGINES: &[(&str, &str, bool)] = &[
+    ("memory", "kv-mem", cfg!(feature = "kv-mem")),
+    ("file", "kv-rocksdb", cfg!(feature = "kv-rocksdb")),
+    ("rocksdb", "kv-rocksdb", cfg!(feature = "kv-rocksdb")),
+    ("tikv", "kv-tikv", cfg!(feature = "kv-tikv")),
+    ("fdb", "kv-fdb", cfg!(feature = "kv-fdb")),
+];
+
+/// Checks that `url` names a datastore engine this build can open
+///
+/// ## Usage
+/// ```no_run
+/// check_url("memory")?;
+/// check_url("file://surreal.db")?;
+/// check_url("tikv://127.0.0.1:2379")?;
+/// ```
+pub fn check_url(url: &str) -> Result<(), String> {
+    let scheme = url.split(':').next().unwrap_or_default();
+
+    match ENGINES.iter().find(|(name, ..)| *name == scheme) {
+        Some((_, _, true)) => Ok(()),
+        Some((_, feature, false)) => Err(format!(
+            "engine '{}' is not enabled, build with `--features {}`",
+            scheme, feature
+        )),
+        None => Err(format!(
+            "unknown engine '{}', expected one of: {}",
+            scheme,
+            ENGINES
+                .iter()
+                .map(|(name, ..)| *name)
+                .collect::<Vec<&str>>()
+                .join(", ")
+        )),
+    }
+}
+
 impl SurrealDBRepo {
-    pub async fn init() -> Result<Self, Error> {
-        let ds = Arc::new(Datastore::new("file://surreal.db").await?);
+    pub async fn init(config: &Config) -> Result<Self, surrealdb::error::Db> {
+        Self::connect(&config.datastore_url, &config.namespace, &config.database).await
+    }
+
+    /// Opens the datastore at `url`, any engine accepted by [`check_url`]
+    pub async fn connect(url: &str, ns: &str, db: &str) -> Result<Self, surrealdb::error::Db> {
+        let ds = Arc::new(Datastore::new(url).await?);
 
-        let ses = Session::for_kv().with_ns("test").with_db("test");
+        let ses = Session::for_kv().with_ns(ns).with_db(db);
 
         Ok(SurrealDBRepo { ses, ds })
     }
+
+    /// Fresh in-memory datastore with every migration applied, meant for tests
+    #[cfg(all(test, feature = "kv-mem"))]
+    pub async fn in_memory() -> Result<Self, Error> {
+        let db = Self::connect("memory", "test", "test").await?;
+        crate::repository::migration::migrate(&db).await?;
+        Ok(db)
+    }
+
+    /// Executes a single statement and returns the value of its first response
+    pub async fn execute(
+        &self,
+        sql: &str,
+        vars: Option<BTreeMap<String, Value>>,
+        strict: bool,
+    ) -> Result<Value, Error> {
+        let ress = self.ds.execute(sql, &self.ses, vars, strict).await?;
+
+        let first_res = ress.into_iter().next().ok_or(Error::StoreNoResponse)?;
+
+        first_res.result.map_err(|err| match err {
+            // Unique indexes are named after their field
+            Db::IndexExists { index, .. } => Error::StoreConflict(index),
+            err => err.into(),
+        })
+    }
+
+    /// Executes every statement of `sql` in non-strict mode, failing on the
+    /// first statement that errors
+    pub async fn execute_script(
+        &self,
+        sql: &str,
+        vars: Option<BTreeMap<String, Value>>,
+    ) -> Result<(), Error> {
+        let ress = self.ds.execute(sql, &self.ses, vars, false).await?;
+
+        for res in ress {
+            res.result?;
+        }
+
+        Ok(())
+    }
+
+    /// Executes a single statement and returns its result set as objects
+    pub async fn execute_objects(
+        &self,
+        sql: &str,
+        vars: Option<BTreeMap<String, Value>>,
+    ) -> Result<Vec<Object>, Error> {
+        let array: Array = W(self.execute(sql, vars, true).await?).try_into()?;
+
+        array.into_iter().map(|value| W(value).try_into()).collect()
+    }
+
+    /// Executes a single statement and converts its result set into `T`
+    pub async fn execute_all<T: TryFrom<Object, Error = Error>>(
+        &self,
+        sql: &str,
+        vars: Option<BTreeMap<String, Value>>,
+    ) -> Result<Vec<T>, Error> {
+        self.execute_objects(sql, vars)
+            .await?
+            .into_iter()
+            .map(T::try_from)
+            .collect()
+    }
+}
+
+/// Comparison used by [`Repository::search_by`] against a single field
+#[derive(Debug, Clone, Copy)]
+pub enum SearchOp {
+    /// `field = $value`
+    Eq,
+    /// `field CONTAINS $value`
+    Contains,
+    /// `field CONTAINSANY $value`
+    ContainsAny,
+    /// `field INSIDE $value`
+    Inside,
+}
+
+impl SearchOp {
+    fn as_sql(&self) -> &'static str {
+        match self {
+            SearchOp::Eq => "=",
+            SearchOp::Contains => "CONTAINS",
+            SearchOp::ContainsAny => "CONTAINSANY",
+            SearchOp::Inside => "INSIDE",
+        }
+    }
+}
+
+/// Generic CRUD access to a single SurrealDB table
+///
+/// Implementors only provide the table name and the model stored records are
+/// converted into, every query is shared.
+///
+/// ## Usage
+/// ```no_run
+/// pub struct TagBMC;
+///
+/// impl Repository for TagBMC {
+///     const TABLE: &'static str = "tag";
+///     type Model = Tag;
+///     type Field = TagField;
+/// }
+///
+/// TagBMC::get(db, "1").await?;
+/// ```
+#[allow(async_fn_in_trait)]
+pub trait Repository {
+    const TABLE: &'static str;
+
+    /// Typed record returned by every query, built from the stored object
+    type Model: TryFrom<Object, Error = Error>;
+
+    /// Fields clients are allowed to search on
+    type Field: Field;
+
+    /// Ordering of list endpoints when the client does not ask for one
+    fn default_sort() -> Vec<Sort<Self::Field>> {
+        Vec::new()
+    }
+
+    /// Builds the full record id (`table:id`) from a bare id
+    fn thing_id(tid: &str) -> String {
+        format!("{}:{}", Self::TABLE, tid)
+    }
+
+    /// Record `tid` of the table, taken as is rather than parsed so any
+    /// client supplied id is safe to bind
+    fn thing(tid: &str) -> Thing {
+        Thing::from((Self::TABLE, tid))
+    }
+
+    /// Lists one page of the records matching `filter`, ordered by `sort`
+    /// or [`Repository::default_sort`] when it is empty
+    async fn list(
+        db: Data<SurrealDBRepo>,
+        filter: Option<&Filter>,
+        sort: Vec<Sort<Self::Field>>,
+        page: &PageParams,
+    ) -> Result<Page<Self::Model>, Error> {
+        let limit = page.limit()?;
+        let sort = match sort.is_empty() {
+            true => Self::default_sort(),
+            false => sort,
+        };
+
+        let mut clause = match filter {
+            Some(filter) => filter.compile::<Self::Field>()?,
+            None => Clause::default(),
+        };
+        let filter_sql = clause.sql.clone();
+
+        let total = match page.total {
+            true => Some(Self::count(db.clone(), &filter_sql, &clause).await?),
+            false => None,
+        };
+
+        let cursor = page.cursor.as_deref().map(Cursor::decode).transpose()?;
+        let dir = cursor.as_ref().map_or(Direction::Next, |c| c.dir);
+
+        let mut conditions: Vec<String> = Vec::new();
+        if !filter_sql.is_empty() {
+            conditions.push(filter_sql);
+        }
+        if let Some(cursor) = &cursor {
+            let th = Self::thing(cursor.id()).into();
+            conditions.push(cursor.condition(&sort, th, &mut clause)?);
+        }
+
+        let mut sql = format!("SELECT * FROM {}", Self::TABLE);
+        if !conditions.is_empty() {
+            sql.push_str(&format!(" WHERE {}", conditions.join(" AND ")));
+        }
+        sql.push_str(&format!(
+            " ORDER BY {} LIMIT {}",
+            order_by(&sort, dir == Direction::Prev),
+            limit + 1
+        ));
+        if let (None, Some(start)) = (&cursor, page.start) {
+            sql.push_str(&format!(" START {}", start));
+        }
+
+        let mut rows = db.execute_objects(&sql, Some(clause.vars)).await?;
+
+        let has_more = rows.len() > limit as usize;
+        rows.truncate(limit as usize);
+        if dir == Direction::Prev {
+            rows.reverse();
+        }
+
+        let (has_next, has_prev) = match (&cursor, dir) {
+            (None, _) => (has_more, page.start.unwrap_or(0) > 0),
+            (Some(_), Direction::Next) => (has_more, true),
+            (Some(_), Direction::Prev) => (true, has_more),
+        };
+
+        let next = match rows.last() {
+            Some(obj) if has_next => Some(Cursor::at(obj, &sort, Direction::Next)?.encode()?),
+            _ => None,
+        };
+        let prev = match rows.first() {
+            Some(obj) if has_prev => Some(Cursor::at(obj, &sort, Direction::Prev)?.encode()?),
+            _ => None,
+        };
+
+        let data = rows
+            .into_iter()
+            .map(Self::Model::try_from)
+            .collect::<Result<Vec<Self::Model>, Error>>()?;
+
+        Ok(Page {
+            data,
+            next,
+            prev,
+            total,
+        })
+    }
+
+    /// Counts the records matching the compiled filter `filter_sql`
+    async fn count(
+        db: Data<SurrealDBRepo>,
+        filter_sql: &str,
+        clause: &Clause,
+    ) -> Result<u64, Error> {
+        let mut sql = format!("SELECT count() FROM {}", Self::TABLE);
+        if !filter_sql.is_empty() {
+            sql.push_str(&format!(" WHERE {}", filter_sql));
+        }
+        sql.push_str(" GROUP ALL;");
+
+        let rows = db.execute_objects(&sql, Some(clause.vars.clone())).await?;
+
+        match rows.into_iter().next() {
+            Some(mut row) => row.x_take_val("count"),
+            None => Ok(0),
+        }
+    }
+
+    async fn search_by_ids(
+        db: Data<SurrealDBRepo>,
+        ids: Vec<&str>,
+        sort: &[Sort<Self::Field>],
+    ) -> Result<Vec<Self::Model>, Error> {
+        let ids: Vec<Value> = ids.iter().map(|id| Self::thing(id).into()).collect();
+
+        Self::search_by(db, "id", SearchOp::Inside, ids, sort).await
+    }
+
+    async fn search_by<V: Into<Value>>(
+        db: Data<SurrealDBRepo>,
+        field: &str,
+        op: SearchOp,
+        value: V,
+        sort: &[Sort<Self::Field>],
+    ) -> Result<Vec<Self::Model>, Error> {
+        let sql = format!(
+            "SELECT * FROM {} WHERE {} {} $value ORDER BY {};",
+            Self::TABLE,
+            field,
+            op.as_sql(),
+            order_by(sort, false)
+        );
+
+        let vars: BTreeMap<String, Value> = map!["value".into() => value.into()];
+
+        db.execute_all(&sql, Some(vars)).await
+    }
+
+    async fn create<T: Creatable>(db: Data<SurrealDBRepo>, data: T) -> Result<Self::Model, Error> {
+        data.check()?;
+
+        let sql = "CREATE type::table($tb) CONTENT $data RETURN *";
+
+        // Versions are owned by the server, every record starts at 0
+        let mut data: Object = W(data.into()).try_into()?;
+        data.insert("version".into(), 0.into());
+
+        let vars: BTreeMap<String, Value> = map![
+            "tb".into() => Self::TABLE.into(),
+            "data".into() => Value::from(data)];
+
+        let first_val = db.execute(sql, Some(vars), false).await?;
+
+        Object::try_from(W(first_val.first()))?.try_into()
+    }
+
+    async fn get(db: Data<SurrealDBRepo>, tid: &str) -> Result<Self::Model, Error> {
+        let sql = "SELECT * FROM $th";
+
+        let vars: BTreeMap<String, Value> = map!["th".into() => Self::thing(tid).into()];
+
+        let result = db.execute(sql, Some(vars), true).await?;
+
+        match result.first() {
+            Value::None => Err(Error::StoreRecordNotFound(Self::thing_id(tid))),
+            value => Object::try_from(W(value))?.try_into(),
+        }
+    }
+
+    /// Merges `data` into the record if it is still at the `expected`
+    /// version, incrementing it in the same statement
+    async fn update<T: Patchable>(
+        db: Data<SurrealDBRepo>,
+        tid: &str,
+        data: T,
+        expected: ExpectedVersion,
+    ) -> Result<Self::Model, Error> {
+        data.check()?;
+        Self::write(db, "MERGE", tid, data.into(), expected).await
+    }
+
+    /// Replaces the whole record, except its id, if it is still at the
+    /// `expected` version, incrementing it in the same statement
+    async fn replace<T: Creatable>(
+        db: Data<SurrealDBRepo>,
+        tid: &str,
+        data: T,
+        expected: ExpectedVersion,
+    ) -> Result<Self::Model, Error> {
+        data.check()?;
+        Self::write(db, "CONTENT", tid, data.into(), expected).await
+    }
+
+    /// Versioned `UPDATE` shared by [`Repository::update`] and
+    /// [`Repository::replace`], `op` is `MERGE` or `CONTENT`
+    async fn write(
+        db: Data<SurrealDBRepo>,
+        op: &str,
+        tid: &str,
+        data: Value,
+        expected: ExpectedVersion,
+    ) -> Result<Self::Model, Error> {
+        let sql = format!(
+            "UPDATE $th {} $data WHERE version = $version RETURN AFTER",
+            op
+        );
+
+        let mut data: Object = W(data).try_into()?;
+        data.remove("id");
+        data.insert("version".into(), expected.next()?.into());
+
+        let vars = map![
+            "th".into() => Self::thing(tid).into(),
+            "data".into() => Value::from(data),
+            "version".into() => expected.value().into()];
+
+        let result = db.execute(&sql, Some(vars.clone()), true).await?;
+
+        match result.first() {
+            // Either gone or changed by another writer in the meantime
+            Value::None => {
+                let current = db.execute("SELECT * FROM $th", Some(vars), true).await?;
+
+                match current.first() {
+                    Value::None => Err(Error::StoreRecordNotFound(Self::thing_id(tid))),
+                    value => {
+                        Err(expected.mismatch(Object::try_from(W(value))?.x_take_val("version")?))
+                    }
+                }
+            }
+            value => Object::try_from(W(value))?.try_into(),
+        }
+    }
+
+    async fn delete(db: Data<SurrealDBRepo>, tid: &str) -> Result<String, Error> {
+        let sql = "DELETE $th RETURN *";
+
+        let vars = map!["th".into() => Self::thing(tid).into()];
+
+        db.execute(sql, Some(vars), false).await?;
+
+        Ok(Self::thing_id(tid))
+    }
+}
+
+#[cfg(all(test, feature = "kv-mem"))]
+mod tests {
+    use super::*;
+    use crate::model::item_model::{Item, ItemBMC, ItemPatch};
+    use crate::repository::migration;
+
+    async fn db() -> Data<SurrealDBRepo> {
+        Data::new(SurrealDBRepo::in_memory().await.unwrap())
+    }
+
+    #[actix_web::test]
+    async fn in_memory_applies_every_migration() {
+        let db = db().await;
+
+        let applied = migration::migrate(&db).await.unwrap();
+
+        assert!(applied.is_empty());
+    }
+
+    #[actix_web::test]
+    async fn create_starts_at_version_0_and_get_reads_it_back() {
+        let db = db().await;
+
+        let mut data = Item::fixture("owner", "first");
+        data.version = 5;
+        let created = ItemBMC::create(db.clone(), data).await.unwrap();
+        let id = created.id.clone().unwrap();
+
+        let read = ItemBMC::get(db, &id).await.unwrap();
+
+        assert_eq!(created.version, 0);
+        assert_eq!(read.name, "first");
+        assert_eq!(read.creation_date, created.creation_date);
+    }
+
+    #[actix_web::test]
+    async fn get_of_a_malformed_id_is_not_found() {
+        let db = db().await;
+
+        let err = ItemBMC::get(db, "a b").await.unwrap_err();
+
+        assert!(matches!(err, Error::StoreRecordNotFound(tid) if tid == "item:a b"));
+    }
+
+    #[actix_web::test]
+    async fn update_increments_the_version_and_rejects_stale_ones() {
+        let db = db().await;
+        let id = ItemBMC::create(db.clone(), Item::fixture("owner", "first"))
+            .await
+            .unwrap()
+            .id
+            .unwrap();
+        let patch = || ItemPatch {
+            name: Some("second".into()),
+            ..Default::default()
+        };
+
+        let updated = ItemBMC::update(db.clone(), &id, patch(), ExpectedVersion::Body(0))
+            .await
+            .unwrap();
+        let stale = ItemBMC::update(db.clone(), &id, patch(), ExpectedVersion::Body(0)).await;
+        let precondition =
+            ItemBMC::update(db.clone(), &id, patch(), ExpectedVersion::IfMatch(0)).await;
+        let missing = ItemBMC::update(db, "missing", patch(), ExpectedVersion::Body(0)).await;
+
+        assert_eq!(updated.version, 1);
+        assert_eq!(updated.name, "second");
+        assert!(matches!(stale, Err(Error::VersionConflict(1))));
+        assert!(matches!(precondition, Err(Error::PreconditionFailed(1))));
+        assert!(matches!(missing, Err(Error::StoreRecordNotFound(_))));
+    }
+
+    #[actix_web::test]
+    async fn create_rejects_invalid_fields() {
+        let db = db().await;
+
+        let mut data = Item::fixture("owner", " ");
+        data.image_url = "ftp://example.com".into();
+        let err = ItemBMC::create(db, data).await.unwrap_err();
+
+        let Error::Validation(violations) = err else {
+            panic!("expected a validation error, got {:?}", err);
+        };
+        let fields: Vec<&str> = violations.iter().map(|v| v.field).collect();
+        assert_eq!(fields, ["name", "image_url"]);
+    }
 }
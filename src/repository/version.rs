@@ -0,0 +1,171 @@
+use actix_web::{
+    http::header::{self, EntityTag},
+    HttpRequest,
+};
+use serde::Deserialize;
+
+use crate::prelude::*;
+
+/// Highest version a record can be at, versions are stored as signed 64 bit
+/// integers
+pub const MAX_VERSION: u64 = i64::MAX as u64;
+
+/// Version a record is expected at for an update to go through, every
+/// successful update increments it
+///
+/// ## Usage
+/// ```no_run
+/// let expected = ExpectedVersion::from_request(&req, item_patch.version)?;
+/// ItemBMC::update(db, "1", data, expected).await?;
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExpectedVersion {
+    /// ETag of the `If-Match` header, answered with a `412` when stale
+    IfMatch(u64),
+    /// `version` of the body, answered with a `409` when stale
+    Body(u64),
+    /// Version the server just read itself, answered with a `409` when stale
+    Read(u64),
+}
+
+impl ExpectedVersion {
+    /// Reads the `If-Match` header, falling back to the `version` of the body,
+    /// `428` when the client sent neither
+    pub fn from_request(req: &HttpRequest, body: Option<u64>) -> Result<Self, Error> {
+        let if_match = match req.headers().get(header::IF_MATCH) {
+            Some(value) => Some(value.to_str().ok().and_then(parse_etag).ok_or_else(|| {
+                Error::InvalidInput("If-Match expects the ETag of the record, e.g. \"3\"".into())
+            })?),
+            None => None,
+        };
+
+        if body.is_some_and(|version| version > MAX_VERSION) {
+            return Err(Error::InvalidFieldValue("version", VERSION_RANGE));
+        }
+
+        match (if_match, body) {
+            (Some(version), _) => Ok(ExpectedVersion::IfMatch(version)),
+            (None, Some(version)) => Ok(ExpectedVersion::Body(version)),
+            (None, None) => Err(Error::VersionRequired),
+        }
+    }
+
+    pub fn value(&self) -> u64 {
+        match self {
+            ExpectedVersion::IfMatch(version)
+            | ExpectedVersion::Body(version)
+            | ExpectedVersion::Read(version) => *version,
+        }
+    }
+
+    /// Version the record is at once written
+    pub fn next(&self) -> Result<u64, Error> {
+        self.value()
+            .checked_add(1)
+            .filter(|version| *version <= MAX_VERSION)
+            .ok_or(Error::InvalidFieldValue("version", VERSION_RANGE))
+    }
+
+    /// Error reporting that the record is at `current` instead
+    pub fn mismatch(&self, current: u64) -> Error {
+        match self {
+            ExpectedVersion::IfMatch(_) => Error::PreconditionFailed(current),
+            ExpectedVersion::Body(_) | ExpectedVersion::Read(_) => Error::VersionConflict(current),
+        }
+    }
+}
+
+/// Body of a `PUT`, the record and the version it was made from, `None`
+/// when the body leaves it out
+///
+/// ## Usage
+/// ```no_run
+/// pub async fn update_item(req: HttpRequest, body: Json<Versioned<Item>>, ...) {
+///     let expected = ExpectedVersion::from_request(&req, body.version)?;
+/// ```
+#[derive(Debug, Deserialize)]
+pub struct Versioned<T> {
+    pub version: Option<u64>,
+    #[serde(flatten)]
+    pub record: T,
+}
+
+/// Accepted versions, reported in errors
+pub const VERSION_RANGE: &str = "an integer between 0 and 9223372036854775807";
+
+/// ETag of a record at `version`, ETags are scoped to the URL of the record
+pub fn etag(version: u64) -> EntityTag {
+    EntityTag::new_strong(version.to_string())
+}
+
+/// Version of a strong `"<version>"` ETag
+fn parse_etag(value: &str) -> Option<u64> {
+    value
+        .trim()
+        .parse::<EntityTag>()
+        .ok()
+        .filter(|tag| !tag.weak)
+        .and_then(|tag| tag.tag().parse().ok())
+        .filter(|version| *version <= MAX_VERSION)
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::TestRequest;
+    use serde_json::json;
+
+    use super::*;
+    use crate::model::item_model::Item;
+
+    fn item(version: Option<u64>) -> serde_json::Value {
+        let mut item = serde_json::to_value(Item::fixture("owner", "name")).unwrap();
+        let item = item.as_object_mut().unwrap();
+        item.remove("version");
+        if let Some(version) = version {
+            item.insert("version".into(), json!(version));
+        }
+        json!(item)
+    }
+
+    #[test]
+    fn records_do_not_need_a_version() {
+        let record: Item = serde_json::from_value(item(None)).unwrap();
+
+        assert_eq!(record.version, 0);
+    }
+
+    #[test]
+    fn versioned_bodies_tell_a_missing_version_apart() {
+        let sent: Versioned<Item> = serde_json::from_value(item(Some(3))).unwrap();
+        let missing: Versioned<Item> = serde_json::from_value(item(None)).unwrap();
+
+        assert_eq!(sent.version, Some(3));
+        assert_eq!(sent.record.name, "name");
+        assert_eq!(missing.version, None);
+    }
+
+    #[test]
+    fn from_request_prefers_if_match_and_requires_a_version() {
+        let plain = TestRequest::default().to_http_request();
+        let if_match = TestRequest::default()
+            .insert_header((header::IF_MATCH, "\"5\""))
+            .to_http_request();
+
+        assert_eq!(
+            ExpectedVersion::from_request(&plain, Some(3)).unwrap(),
+            ExpectedVersion::Body(3)
+        );
+        assert_eq!(
+            ExpectedVersion::from_request(&if_match, Some(3)).unwrap(),
+            ExpectedVersion::IfMatch(5)
+        );
+        assert!(matches!(
+            ExpectedVersion::from_request(&plain, None),
+            Err(Error::VersionRequired)
+        ));
+        assert!(matches!(
+            ExpectedVersion::from_request(&plain, Some(MAX_VERSION + 1)),
+            Err(Error::InvalidFieldValue("version", _))
+        ));
+    }
+}
@@ -1,3 +1,4 @@
 // utils/mod.rs
 pub mod macros;
-pub mod try_froms;
\ No newline at end of file
+pub mod try_froms;
+pub mod x_take;
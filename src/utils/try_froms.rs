@@ -1,7 +1,7 @@
 // https://github.com/rust-awesome-app/template-app-base/blob/main/src-tauri/src/store/try_froms.rs
 
 use crate::prelude::*;
-use surrealdb::sql::{Array, Object, Value};
+use surrealdb::sql::{Array, Datetime, Object, Thing, Value};
 
 impl TryFrom<W<Value>> for Object {
     type Error = Error;
@@ -53,4 +53,42 @@ impl TryFrom<W<Value>> for String {
 			_ => Err(Error::XValueNotOfType("String")),
 		}
 	}
-}
\ No newline at end of file
+}
+
+impl TryFrom<W<Value>> for u64 {
+	type Error = Error;
+	fn try_from(val: W<Value>) -> Result<u64, Error> {
+		match val.0 {
+			Value::Number(obj) => u64::try_from(obj.as_int()).map_err(|_| Error::XValueNotOfType("u64")),
+			_ => Err(Error::XValueNotOfType("u64")),
+		}
+	}
+}
+
+impl TryFrom<W<Value>> for Thing {
+	type Error = Error;
+	fn try_from(val: W<Value>) -> Result<Thing, Error> {
+		match val.0 {
+			Value::Thing(thing) => Ok(thing),
+			_ => Err(Error::XValueNotOfType("Thing")),
+		}
+	}
+}
+
+impl TryFrom<W<Value>> for Vec<String> {
+	type Error = Error;
+	fn try_from(val: W<Value>) -> Result<Vec<String>, Error> {
+		let array: Array = val.try_into()?;
+		array.into_iter().map(|value| W(value).try_into()).collect()
+	}
+}
+
+impl TryFrom<W<Value>> for Datetime {
+	type Error = Error;
+	fn try_from(val: W<Value>) -> Result<Datetime, Error> {
+		match val.0 {
+			Value::Datetime(datetime) => Ok(datetime),
+			_ => Err(Error::XValueNotOfType("Datetime")),
+		}
+	}
+}
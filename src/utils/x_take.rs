@@ -0,0 +1,38 @@
+// https://github.com/rust-awesome-app/template-app-base/blob/main/src-tauri/src/utils/x_take.rs
+
+use crate::prelude::*;
+use surrealdb::sql::Object;
+
+/// Remove the value `k` from `self` and convert it to `T`
+pub trait XTakeImpl<T> {
+    fn x_take_impl(&mut self, k: &str) -> Result<Option<T>, Error>;
+}
+
+/// Takes a required property, failing with [`Error::XPropertyNotFound`] when it is missing
+pub trait XTakeVal {
+    fn x_take_val<T>(&mut self, k: &str) -> Result<T, Error>
+    where
+        Self: XTakeImpl<T>;
+}
+
+impl<S> XTakeVal for S {
+    fn x_take_val<T>(&mut self, k: &str) -> Result<T, Error>
+    where
+        Self: XTakeImpl<T>,
+    {
+        let val: Option<T> = XTakeImpl::x_take_impl(self, k)?;
+        val.ok_or_else(|| Error::XPropertyNotFound(k.to_string()))
+    }
+}
+
+impl<T> XTakeImpl<T> for Object
+where
+    T: TryFrom<W<surrealdb::sql::Value>, Error = Error>,
+{
+    fn x_take_impl(&mut self, k: &str) -> Result<Option<T>, Error> {
+        match self.remove(k) {
+            None => Ok(None),
+            Some(v) => W(v).try_into().map(Some),
+        }
+    }
+}
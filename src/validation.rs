@@ -0,0 +1,115 @@
+use serde::Serialize;
+use url::Url;
+
+use crate::prelude::*;
+
+/// Longest `name` of an item or a user, in characters
+pub const NAME_MAX: usize = 200;
+
+/// Presence states a user can be in
+pub const ONLINE_STATES: &[&str] = &["online", "away", "busy", "offline"];
+
+/// Field that failed validation, named as in the API
+#[derive(Debug, Clone, Serialize)]
+pub struct Violation {
+    pub field: &'static str,
+    pub message: String,
+}
+
+/// Payload checked before it is written, failing with a `422` listing every
+/// invalid field
+///
+/// Rules take an `Option` so full records and patches share them, a patch
+/// only checks the fields it sets.
+///
+/// ## Usage
+/// ```no_run
+/// impl Validate for ItemPatch {
+///     fn validate(&self, v: &mut Validator) {
+///         v.name("name", self.name.as_deref());
+///         v.url("image_url", self.image_url.as_deref());
+///     }
+/// }
+///
+/// item_patch.check()?;
+/// ```
+pub trait Validate {
+    fn validate(&self, v: &mut Validator);
+
+    fn check(&self) -> Result<(), Error> {
+        let mut v = Validator::default();
+        self.validate(&mut v);
+        v.finish()
+    }
+}
+
+/// Collects the violations of a payload
+#[derive(Debug, Default)]
+pub struct Validator {
+    violations: Vec<Violation>,
+}
+
+impl Validator {
+    pub fn finish(self) -> Result<(), Error> {
+        match self.violations.is_empty() {
+            true => Ok(()),
+            false => Err(Error::Validation(self.violations)),
+        }
+    }
+
+    fn fail(&mut self, field: &'static str, message: impl Into<String>) {
+        self.violations.push(Violation {
+            field,
+            message: message.into(),
+        });
+    }
+
+    /// Non blank and at most [`NAME_MAX`] characters
+    pub fn name(&mut self, field: &'static str, value: Option<&str>) {
+        match value {
+            Some(value) if value.trim().is_empty() => self.fail(field, "must not be empty"),
+            Some(value) if value.chars().count() > NAME_MAX => {
+                self.fail(field, format!("must be at most {} characters", NAME_MAX))
+            }
+            _ => (),
+        }
+    }
+
+    /// Single `@` between a local part and a dotted domain, no whitespace
+    pub fn email(&mut self, field: &'static str, value: Option<&str>) {
+        let Some(value) = value else { return };
+
+        let valid = value.len() <= 254
+            && !value.chars().any(char::is_whitespace)
+            && value.split_once('@').is_some_and(|(local, domain)| {
+                !local.is_empty()
+                    && !domain.contains('@')
+                    && domain.split('.').count() > 1
+                    && domain.split('.').all(|label| !label.is_empty())
+            });
+
+        if !valid {
+            self.fail(field, "must be an email address");
+        }
+    }
+
+    /// Absolute `http` or `https` URL, empty when there is none
+    pub fn url(&mut self, field: &'static str, value: Option<&str>) {
+        let Some(value) = value.filter(|value| !value.is_empty()) else {
+            return;
+        };
+
+        let valid = Url::parse(value)
+            .is_ok_and(|url| matches!(url.scheme(), "http" | "https") && url.host_str().is_some());
+
+        if !valid {
+            self.fail(field, "must be an http or https URL");
+        }
+    }
+
+    pub fn one_of(&mut self, field: &'static str, value: Option<&str>, allowed: &[&str]) {
+        if value.is_some_and(|value| !allowed.contains(&value)) {
+            self.fail(field, format!("must be one of {}", allowed.join(", ")));
+        }
+    }
+}